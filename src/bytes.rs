@@ -0,0 +1,372 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use crate::{Builder, Constraint, NodeId, OpKind, RomHandle};
+
+const INPUT: u8 = 0;
+const CONSTANT: u8 = 1;
+const HINT: u8 = 2;
+const ADD: u8 = 3;
+const SUB: u8 = 4;
+const MUL: u8 = 5;
+const OVERFLOWING_ADD: u8 = 6;
+const OVERFLOWING_MUL: u8 = 7;
+const MIN: u8 = 8;
+const MAX: u8 = 9;
+const ABS_DIFF: u8 = 10;
+const ROM_READ: u8 = 11;
+const SCALAR_ADD: u8 = 12;
+const SCALAR_MUL: u8 = 13;
+const SUM: u8 = 14;
+const PRODUCT: u8 = 15;
+const CUSTOM: u8 = 16;
+const REM: u8 = 17;
+
+/// Bytes that aren't a graph written by `Builder::to_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes ended in the middle of the graph.
+    UnexpectedEnd,
+    /// There are bytes left after the graph.
+    TrailingBytes,
+    /// A node has a tag that isn't a known operation.
+    InvalidTag(u8),
+    /// A number is too large for where it is used.
+    InvalidNumber,
+    /// A node has the wrong number of parents for its operation.
+    InvalidParents(NodeId),
+    /// A node reads from a table that doesn't exist.
+    InvalidRom(NodeId),
+    /// A node is a hint or custom operation, whose function isn't encoded.
+    Unencodable(NodeId),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of encoded graph"),
+            DecodeError::TrailingBytes => write!(f, "unexpected bytes after encoded graph"),
+            DecodeError::InvalidTag(tag) => write!(f, "unknown node tag {}", tag),
+            DecodeError::InvalidNumber => write!(f, "encoded number is out of range"),
+            DecodeError::InvalidParents(id) => write!(f, "node {} has the wrong number of parents", id),
+            DecodeError::InvalidRom(id) => write!(f, "node {} reads from a table that doesn't exist", id),
+            DecodeError::Unencodable(id) => {
+                write!(f, "node {} is a hint or custom operation and can't be decoded", id)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+fn write_number(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads the numbers written by `write_number` from the front of a slice.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let part = u64::from(byte & 0x7f);
+            if shift == 63 && part > 1 {
+                return Err(DecodeError::InvalidNumber);
+            }
+            value |= part << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidNumber)
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        u32::try_from(self.number()?).map_err(|_| DecodeError::InvalidNumber)
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        usize::try_from(self.number()?).map_err(|_| DecodeError::InvalidNumber)
+    }
+}
+
+impl Builder {
+    /// Encodes the structure of the graph as bytes: its tables, nodes and
+    /// constraints. Numbers are written as variable-length integers, so
+    /// small graphs take few bytes.
+    ///
+    /// The bit width (see `Builder::with_bit_width`) is encoded too. Node
+    /// values other than constants, metadata and output groups aren't
+    /// encoded. Hints and custom operations are written without their
+    /// functions, so `from_bytes` can't decode them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order: Vec<NodeId> = (0..self.nodes.len()).collect();
+        let rom_order: Vec<usize> = (0..self.roms.len()).collect();
+        self.encode(&order, &rom_order, &self.constraints)
+    }
+
+    /// Encodes the graph like `to_bytes`, but with the nodes in a canonical
+    /// order that doesn't depend on the order they were built in, so
+    /// equivalent graphs give identical bytes.
+    ///
+    /// Tables are sorted by their contents, and table reads refer to them by
+    /// their sorted position. Nodes are written level by level (see
+    /// `levels`), and sorted within a level by what they compute from which
+    /// already written nodes. Inputs keep their relative order, since that is
+    /// how they are told apart. Constraints are sorted, with the smaller node
+    /// first. Nodes on a cycle are written last, in id order.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut rom_order: Vec<usize> = (0..self.roms.len()).collect();
+        rom_order.sort_by(|&a, &b| self.roms[a].cmp(&self.roms[b]));
+        let mut rom_position = vec![0; self.roms.len()];
+        for (new_index, &index) in rom_order.iter().enumerate() {
+            rom_position[index] = new_index;
+        }
+
+        let mut canonical: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut order: Vec<NodeId> = Vec::with_capacity(self.nodes.len());
+        let mut input_count = 0u64;
+        for level in self.levels() {
+            let mut keyed: Vec<_> = level
+                .into_iter()
+                .map(|node_id| {
+                    let node = &self.nodes[node_id];
+                    let (rank, value) = if node.is_constant {
+                        (
+                            1,
+                            u64::from(node.value.borrow().expect("Constants always have a value")),
+                        )
+                    } else if node.is_input() {
+                        input_count += 1;
+                        (0, input_count)
+                    } else {
+                        (2, 0)
+                    };
+                    let kind = node.kind.map(|kind| match kind {
+                        OpKind::RomRead(rom) => OpKind::RomRead(RomHandle(rom_position[rom.0])),
+                        kind => kind,
+                    });
+                    let parents: Vec<Option<usize>> = node.parents.iter().map(|&id| canonical[id]).collect();
+                    (
+                        (rank, value, kind, node.is_hint, parents),
+                        node_id,
+                    )
+                })
+                .collect();
+            keyed.sort();
+            for (_, node_id) in keyed {
+                canonical[node_id] = Some(order.len());
+                order.push(node_id);
+            }
+        }
+        for (node_id, slot) in canonical.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = Some(order.len());
+                order.push(node_id);
+            }
+        }
+
+        let position = |id: NodeId| canonical.get(id).copied().flatten().unwrap_or(id);
+        let mut constraints: Vec<Constraint> = self
+            .constraints
+            .iter()
+            .map(|&(a, b)| {
+                let (a, b) = (position(a), position(b));
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        constraints.sort_unstable();
+        self.encode(&order, &rom_order, &constraints)
+    }
+
+    /// Writes the tables in `rom_order`, then the nodes in `order` with their
+    /// parents and tables renumbered by position in `order` and `rom_order`,
+    /// then `constraints` as given.
+    fn encode(&self, order: &[NodeId], rom_order: &[usize], constraints: &[Constraint]) -> Vec<u8> {
+        let mut position: Vec<NodeId> = (0..self.nodes.len()).collect();
+        for (new_id, &node_id) in order.iter().enumerate() {
+            position[node_id] = new_id;
+        }
+        let mut rom_position: Vec<usize> = (0..self.roms.len()).collect();
+        for (new_index, &index) in rom_order.iter().enumerate() {
+            rom_position[index] = new_index;
+        }
+        let mut out = Vec::new();
+        write_number(&mut out, self.bit_width().into());
+        write_number(&mut out, self.roms.len() as u64);
+        for rom in rom_order.iter().map(|&index| &self.roms[index]) {
+            write_number(&mut out, rom.len() as u64);
+            for &value in rom.iter() {
+                write_number(&mut out, value.into());
+            }
+        }
+
+        write_number(&mut out, order.len() as u64);
+        for node in order.iter().map(|&node_id| &self.nodes[node_id]) {
+            if node.is_constant {
+                out.push(CONSTANT);
+                write_number(
+                    &mut out,
+                    node.value.borrow().expect("Constants always have a value").into(),
+                );
+                continue;
+            }
+            let tag = match node.kind {
+                _ if node.is_hint => HINT,
+                None => INPUT,
+                Some(OpKind::Add) => ADD,
+                Some(OpKind::Sub) => SUB,
+                Some(OpKind::Mul) => MUL,
+                Some(OpKind::OverflowingAdd) => OVERFLOWING_ADD,
+                Some(OpKind::OverflowingMul) => OVERFLOWING_MUL,
+                Some(OpKind::Min) => MIN,
+                Some(OpKind::Max) => MAX,
+                Some(OpKind::AbsDiff) => ABS_DIFF,
+                Some(OpKind::Rem) => REM,
+                Some(OpKind::RomRead(_)) => ROM_READ,
+                Some(OpKind::ScalarAdd(_)) => SCALAR_ADD,
+                Some(OpKind::ScalarMul(_)) => SCALAR_MUL,
+                Some(OpKind::Sum) => SUM,
+                Some(OpKind::Product) => PRODUCT,
+                Some(OpKind::Custom(_)) => CUSTOM,
+                Some(OpKind::Hint) => HINT,
+            };
+            out.push(tag);
+            if tag == INPUT {
+                continue;
+            }
+            match node.kind {
+                Some(OpKind::RomRead(rom)) => write_number(&mut out, rom_position[rom.0] as u64),
+                Some(OpKind::ScalarAdd(c) | OpKind::ScalarMul(c)) => write_number(&mut out, c.into()),
+                _ => {}
+            }
+            write_number(&mut out, node.parents.len() as u64);
+            for &parent in &node.parents {
+                write_number(&mut out, position.get(parent).copied().unwrap_or(parent) as u64);
+            }
+        }
+
+        write_number(&mut out, constraints.len() as u64);
+        for &(a, b) in constraints {
+            write_number(&mut out, a as u64);
+            write_number(&mut out, b as u64);
+        }
+        out
+    }
+
+    /// Decodes a graph written by `to_bytes`. The result is unfilled, apart
+    /// from its constants.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Builder, DecodeError> {
+        let mut reader = Reader { bytes };
+        let bits = reader.u32()?;
+        if !(1..=32).contains(&bits) {
+            return Err(DecodeError::InvalidNumber);
+        }
+        let mut builder = Builder::with_bit_width(bits);
+        for _ in 0..reader.usize()? {
+            let values = (0..reader.usize()?)
+                .map(|_| reader.u32())
+                .collect::<Result<Vec<u32>, _>>()?;
+            builder.rom(&values);
+        }
+
+        for node_id in 0..reader.usize()? {
+            let tag = reader.byte()?;
+            match tag {
+                INPUT => {
+                    builder.init();
+                    continue;
+                }
+                CONSTANT => {
+                    let value = reader.u32()?;
+                    builder.constant(value);
+                    continue;
+                }
+                HINT | CUSTOM => return Err(DecodeError::Unencodable(node_id)),
+                _ => {}
+            }
+            let extra = match tag {
+                ROM_READ | SCALAR_ADD | SCALAR_MUL => reader.u32()?,
+                _ => 0,
+            };
+            let parents = (0..reader.usize()?)
+                .map(|_| reader.usize())
+                .collect::<Result<Vec<NodeId>, _>>()?;
+            let kind = match (tag, parents.as_slice()) {
+                (ADD, &[a, b]) => {
+                    builder.add(&a, &b);
+                    continue;
+                }
+                (SUB, &[a, b]) => {
+                    builder.sub(&a, &b);
+                    continue;
+                }
+                (MUL, &[a, b]) => {
+                    builder.mul(&a, &b);
+                    continue;
+                }
+                (OVERFLOWING_ADD, &[a, b]) => {
+                    builder.overflowing_add(&a, &b);
+                    continue;
+                }
+                (OVERFLOWING_MUL, &[a, b]) => {
+                    builder.overflowing_mul(&a, &b);
+                    continue;
+                }
+                (MIN, &[a, b]) => {
+                    builder.min(&a, &b);
+                    continue;
+                }
+                (MAX, &[a, b]) => {
+                    builder.max(&a, &b);
+                    continue;
+                }
+                (ABS_DIFF, &[a, b]) => {
+                    builder.abs_diff(&a, &b);
+                    continue;
+                }
+                (REM, &[a, m]) => {
+                    builder.rem_node(&a, &m);
+                    continue;
+                }
+                (ROM_READ, &[index]) => {
+                    builder
+                        .rom_read(&RomHandle(extra as usize), &index)
+                        .map_err(|_| DecodeError::InvalidRom(node_id))?;
+                    continue;
+                }
+                (SCALAR_ADD, &[_]) => OpKind::ScalarAdd(extra),
+                (SCALAR_MUL, &[_]) => OpKind::ScalarMul(extra),
+                (SUM, [_, ..]) => OpKind::Sum,
+                (PRODUCT, [_, ..]) => OpKind::Product,
+                (ADD..=PRODUCT | REM, _) => return Err(DecodeError::InvalidParents(node_id)),
+                _ => return Err(DecodeError::InvalidTag(tag)),
+            };
+            builder.add_operation(kind, parents, kind.rewrite_operation());
+        }
+
+        for _ in 0..reader.usize()? {
+            let a = reader.usize()?;
+            let b = reader.usize()?;
+            builder.assert_equal(a, b);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(builder)
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{Constraint, NodeId};
+
+/// Structural differences between two graphs, matching nodes by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphDiff {
+    /// Nodes that only exist in the other graph.
+    pub added_nodes: Vec<NodeId>,
+    /// Nodes that only exist in this graph.
+    pub removed_nodes: Vec<NodeId>,
+    /// Nodes in both graphs whose operation, parents or constant value differ.
+    pub changed_nodes: Vec<NodeId>,
+    /// Constraints that only exist in the other graph.
+    pub added_constraints: Vec<Constraint>,
+    /// Constraints that only exist in this graph.
+    pub removed_constraints: Vec<Constraint>,
+}
+
+impl GraphDiff {
+    /// Returns `true` if the graphs are structurally identical.
+    pub fn is_empty(&self) -> bool {
+        self == &GraphDiff::default()
+    }
+}
@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use crate::NodeId;
+
+/// Errors returned by the fallible `Builder` APIs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuilderError {
+    /// More input values were given than there are nodes in the graph.
+    TooManyInputs { given: usize, nodes: usize },
+    /// A witness doesn't have exactly one value per node.
+    WitnessLength { given: usize, nodes: usize },
+    /// A node id doesn't refer to a node in the graph.
+    InvalidNode(NodeId),
+    /// An input value was given for a node that isn't an input.
+    NotAnInput(NodeId),
+    /// No custom operation is registered under a name.
+    UnknownOp(String),
+    /// A ROM handle doesn't refer to a table in the builder.
+    InvalidRom(usize),
+    /// A node was changed as a constant, but isn't one.
+    NotAConstant(NodeId),
+    /// A constant was changed after its value was copied into other nodes.
+    FoldedConstant(NodeId),
+    /// A node that needed a value hasn't been filled.
+    Unfilled(NodeId),
+    /// No value was given for an input that a constraint depends on.
+    MissingInput(NodeId),
+    /// An operation node has no parents to compute its value from.
+    NoParents(NodeId),
+    /// A node lies on a dependency cycle.
+    Cycle(NodeId),
+    /// Computing a node's value failed.
+    Eval(NodeId, EvalError),
+    /// A recorded hint or custom operation can't be replayed, since its
+    /// function isn't recorded. Only returned with the `record` feature.
+    UnreplayableHint(usize),
+    /// A recorded bit width isn't the first operation, so it can't apply to
+    /// the whole replayed graph. Only returned with the `record` feature.
+    MisplacedBitWidth(usize),
+    /// A constraint doesn't hold.
+    Constraint(ConstraintError),
+}
+
+impl Display for BuilderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuilderError::TooManyInputs { given, nodes } => {
+                write!(f, "got {} input values for a graph with {} nodes", given, nodes)
+            }
+            BuilderError::WitnessLength { given, nodes } => {
+                write!(f, "got {} witness values for a graph with {} nodes", given, nodes)
+            }
+            BuilderError::InvalidNode(id) => write!(f, "node {} does not exist", id),
+            BuilderError::NotAnInput(id) => write!(f, "node {} is not an input node", id),
+            BuilderError::UnknownOp(name) => write!(f, "no operation registered as {:?}", name),
+            BuilderError::InvalidRom(rom) => write!(f, "table {} does not exist", rom),
+            BuilderError::NotAConstant(id) => write!(f, "node {} is not a constant node", id),
+            BuilderError::FoldedConstant(id) => {
+                write!(f, "constant node {} has been folded into other nodes", id)
+            }
+            BuilderError::Unfilled(id) => write!(f, "node {} has not been filled", id),
+            BuilderError::MissingInput(id) => write!(f, "no value given for input node {}", id),
+            BuilderError::NoParents(id) => write!(f, "operation node {} has no parents", id),
+            BuilderError::Cycle(id) => write!(f, "node {} lies on a dependency cycle", id),
+            BuilderError::Eval(id, err) => write!(f, "failed to evaluate node {}: {}", id, err),
+            BuilderError::UnreplayableHint(position) => {
+                write!(f, "recorded operation {} is a hint and can't be replayed", position)
+            }
+            BuilderError::MisplacedBitWidth(position) => {
+                write!(f, "recorded operation {} sets the bit width after the first operation", position)
+            }
+            BuilderError::Constraint(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for BuilderError {}
+
+impl From<ConstraintError> for BuilderError {
+    fn from(err: ConstraintError) -> Self {
+        BuilderError::Constraint(err)
+    }
+}
+
+/// Errors raised by an operation while computing a node's value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// A ROM was read at an index past its end.
+    IndexOutOfBounds { index: u32, len: usize },
+    /// A strict `hint_f64` produced NaN, an infinity, a fraction or a value
+    /// outside the `u32` range.
+    LossyHint,
+    /// A remainder was taken with a modulus of 0.
+    DivisionByZero,
+    /// An `add` or `mul` result doesn't fit in a `u32`.
+    Overflow,
+    /// A hint created with `Builder::hint_dynamic` was evaluated outside of
+    /// filling the builder.
+    DynamicHint,
+    /// A hint returned different values when evaluated twice on the same
+    /// inputs. Only checked in debug builds.
+    NondeterministicHint { first: u32, second: u32 },
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} is out of bounds for a table of length {}", index, len)
+            }
+            EvalError::LossyHint => write!(f, "hint result can't be represented exactly as a u32"),
+            EvalError::DivisionByZero => write!(f, "remainder with a modulus of 0"),
+            EvalError::Overflow => write!(f, "result overflows a u32"),
+            EvalError::DynamicHint => write!(f, "dynamic hint can only be evaluated while filling"),
+            EvalError::NondeterministicHint { first, second } => {
+                write!(f, "hint returned {} and then {} for the same inputs", first, second)
+            }
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+/// An `assert_equal` constraint that doesn't hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintError {
+    /// Position of the constraint, in the order it was added.
+    pub index: usize,
+    pub left: NodeId,
+    pub right: NodeId,
+    pub left_value: Option<u32>,
+    pub right_value: Option<u32>,
+}
+
+impl Display for ConstraintError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "constraint {} failed: node {} ({}) != node {} ({})",
+            self.index,
+            self.left,
+            display_value(self.left_value),
+            self.right,
+            display_value(self.right_value)
+        )
+    }
+}
+
+impl Error for ConstraintError {}
+
+fn display_value(value: Option<u32>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "unfilled".to_string(),
+    }
+}
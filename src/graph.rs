@@ -0,0 +1,37 @@
+use crate::{Builder, NodeId};
+
+/// A finished computational graph, created with `Builder::build`.
+///
+/// Unlike a `Builder`, a `Graph` can't be extended and never changes its
+/// node values: every evaluation works on its own buffer.
+pub struct Graph {
+    builder: Builder,
+}
+
+impl Graph {
+    pub(crate) fn new(builder: Builder) -> Self {
+        Self { builder }
+    }
+
+    /// Returns the number of nodes in the graph.
+    pub fn len(&self) -> usize {
+        self.builder.nodes.len()
+    }
+
+    /// Returns `true` if the graph has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.builder.nodes.is_empty()
+    }
+
+    /// Evaluates the graph for some inputs, given as `(handle, value)`
+    /// pairs, returning the value of every node. See
+    /// `Builder::evaluate_into`.
+    pub fn evaluate(&self, inputs: &[(NodeId, u32)]) -> Vec<Option<u32>> {
+        self.builder.evaluate_into(inputs)
+    }
+
+    /// Returns the value the node held when the graph was built.
+    pub fn get_value(&self, id: NodeId) -> Option<u32> {
+        self.builder.get_value(id)
+    }
+}
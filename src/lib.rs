@@ -1,32 +1,223 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Formatter};
 use std::rc::Rc;
 
-type NodeId = usize;
+mod bytes;
+mod diff;
+mod error;
+mod graph;
+mod r1cs;
+#[cfg(feature = "record")]
+mod record;
+
+pub use bytes::DecodeError;
+pub use diff::GraphDiff;
+pub use error::{BuilderError, ConstraintError, EvalError};
+pub use graph::Graph;
+pub use r1cs::{LinearCombination, R1cs};
+#[cfg(feature = "record")]
+pub use record::{ParseOpError, RecordedOp};
+
+pub type NodeId = usize;
+/// An equality between two nodes, as added by `Builder::assert_equal`.
+pub type Constraint = (NodeId, NodeId);
+type Operation = Rc<dyn Fn(&[u32]) -> Result<u32, EvalError>>;
+type DynamicHint = Rc<dyn Fn(&Builder) -> u32>;
+
+/// The kind of operation a node computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OpKind {
+    Add,
+    Sub,
+    Mul,
+    OverflowingAdd,
+    OverflowingMul,
+    Min,
+    Max,
+    AbsDiff,
+    /// The remainder of dividing the first parent by the second, see
+    /// `Builder::rem_node`.
+    Rem,
+    /// Reads from the given table, see `Builder::rom_read`.
+    RomRead(RomHandle),
+    /// Adds a fixed constant to the single parent, see `specialize_constants`.
+    ScalarAdd(u32),
+    /// Multiplies the single parent by a fixed constant, see
+    /// `specialize_constants`.
+    ScalarMul(u32),
+    /// Adds all parents, see `flatten_associative`.
+    Sum,
+    /// Multiplies all parents, see `flatten_associative`.
+    Product,
+    /// An operation registered with `Builder::register_op`, by registration
+    /// order.
+    Custom(usize),
+    /// A hint, see `Builder::hint`. Hint nodes don't carry a kind, so this
+    /// is only used as the key for their weight in `Builder::estimated_cost`.
+    Hint,
+}
+
+impl OpKind {
+    /// Returns `true` if this is an overflowing operation whose result is
+    /// larger than `mask` for the given parent values, and so wraps around.
+    fn overflows(self, values: &[u32], mask: u32) -> bool {
+        match self {
+            OpKind::OverflowingAdd => u64::from(values[0]) + u64::from(values[1]) > u64::from(mask),
+            OpKind::OverflowingMul => u64::from(values[0]) * u64::from(values[1]) > u64::from(mask),
+            _ => false,
+        }
+    }
+
+    /// Returns the operation with wrapping arithmetic for the kinds whose
+    /// usual operation fails with `EvalError::Overflow`.
+    fn wrapping_operation(self) -> Option<Operation> {
+        Some(match self {
+            OpKind::Add => Rc::new(|values| Ok(values[0].wrapping_add(values[1]))),
+            OpKind::Mul => Rc::new(|values| Ok(values[0].wrapping_mul(values[1]))),
+            OpKind::ScalarAdd(c) => Rc::new(move |values| Ok(values[0].wrapping_add(c))),
+            OpKind::ScalarMul(c) => Rc::new(move |values| Ok(values[0].wrapping_mul(c))),
+            OpKind::Sum => Rc::new(|values| Ok(values.iter().fold(0, |acc: u32, &value| acc.wrapping_add(value)))),
+            OpKind::Product => Rc::new(|values| Ok(values.iter().fold(1, |acc: u32, &value| acc.wrapping_mul(value)))),
+            _ => return None,
+        })
+    }
+
+    /// Returns the operation for the kinds that are only created by graph
+    /// rewrites such as `Builder::specialize_constants`.
+    fn rewrite_operation(self) -> Operation {
+        match self {
+            OpKind::ScalarAdd(c) => Rc::new(move |values| values[0].checked_add(c).ok_or(EvalError::Overflow)),
+            OpKind::ScalarMul(c) => Rc::new(move |values| values[0].checked_mul(c).ok_or(EvalError::Overflow)),
+            OpKind::Sum => Rc::new(|values| {
+                values
+                    .iter()
+                    .try_fold(0u32, |acc, &value| acc.checked_add(value))
+                    .ok_or(EvalError::Overflow)
+            }),
+            OpKind::Product => Rc::new(|values| {
+                values
+                    .iter()
+                    .try_fold(1u32, |acc, &value| acc.checked_mul(value))
+                    .ok_or(EvalError::Overflow)
+            }),
+            _ => unreachable!("{:?} isn't created by a rewrite", self),
+        }
+    }
+}
+
+/// A handle to a read-only table created with `Builder::rom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RomHandle(usize);
+
+/// The node values of a graph, saved with `Builder::save_state`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphState {
+    values: Vec<(Option<u32>, bool)>,
+    solved_inputs: Vec<NodeId>,
+}
+
+/// The outcome of `Builder::run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult {
+    /// The value of every node that no other node uses, in id order. Nodes
+    /// that couldn't be computed are `None`.
+    pub outputs: Vec<(NodeId, Option<u32>)>,
+    /// Whether every constraint holds, as in `Builder::is_satisfied`.
+    /// Always `false` if filling failed.
+    pub satisfied: bool,
+    /// The error that stopped filling, if any.
+    pub error: Option<BuilderError>,
+}
+
+/// How a node was created, as listed by `Builder::op_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpLogEntry {
+    Input,
+    Constant(u32),
+    /// A hint over the given dependencies.
+    Hint(Vec<NodeId>),
+    /// An operation of the given kind over the given parents.
+    Operation(OpKind, Vec<NodeId>),
+}
 
 /// A node in the computational graph.
 pub struct Node {
     value: RefCell<Option<u32>>,
+    overflowed: Cell<bool>,
+    evaluations: Cell<usize>,
+    metadata: RefCell<Option<String>>,
+    tag: RefCell<Option<String>>,
     is_hint: bool,
+    is_constant: bool,
+    /// Set on a constant whose value has been copied into other nodes, see
+    /// `Builder::set_constant`.
+    folded: Cell<bool>,
+    kind: Option<OpKind>,
     parents: Vec<NodeId>,
-    operation: RefCell<Option<Box<dyn Fn(u32, u32) -> u32>>>,
+    operation: RefCell<Option<Operation>>,
+    /// Set for hints created with `Builder::hint_dynamic`, which read the
+    /// builder instead of their parent values.
+    dynamic: Option<DynamicHint>,
 }
 
 impl Node {
     pub fn new(value: Option<u32>, is_hint: bool, parents: Vec<NodeId>) -> Self {
         Self {
             value: RefCell::new(value),
+            overflowed: Cell::new(false),
+            evaluations: Cell::new(0),
+            metadata: RefCell::new(None),
+            tag: RefCell::new(None),
             is_hint,
+            is_constant: false,
+            folded: Cell::new(false),
+            kind: None,
             parents,
             operation: RefCell::new(None),
+            dynamic: None,
         }
     }
 }
 
+impl Node {
+    fn is_input(&self) -> bool {
+        !self.is_constant && self.operation.borrow().is_none()
+    }
+
+    /// Returns a copy of this node that reads from `parents` instead.
+    fn with_parents(&self, parents: Vec<NodeId>) -> Node {
+        let mut node = Node::new(*self.value.borrow(), self.is_hint, parents);
+        node.overflowed.set(self.overflowed.get());
+        node.evaluations.set(self.evaluations.get());
+        *node.metadata.borrow_mut() = self.metadata.borrow().clone();
+        *node.tag.borrow_mut() = self.tag.borrow().clone();
+        node.is_constant = self.is_constant;
+        node.folded.set(self.folded.get());
+        node.kind = self.kind;
+        *node.operation.borrow_mut() = self.operation.borrow().clone();
+        node.dynamic = self.dynamic.clone();
+        node
+    }
+
+    /// Returns `true` if both nodes compute the same thing from the same
+    /// parent ids. Hint functions can't be compared, so any two hints over
+    /// the same parents are treated as the same.
+    fn same_operation(&self, other: &Node) -> bool {
+        let constant_value = |node: &Node| node.is_constant.then(|| *node.value.borrow());
+        self.is_hint == other.is_hint
+            && self.kind == other.kind
+            && self.parents == other.parents
+            && self.is_input() == other.is_input()
+            && constant_value(self) == constant_value(other)
+    }
+}
+
 impl Debug for Node {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Node")
             .field("value", &self.value)
+            .field("metadata", &self.metadata)
             .field("is_hint", &self.is_hint)
             .field("parents", &self.parents)
             .finish()
@@ -36,8 +227,25 @@ impl Debug for Node {
 /// A builder that will be used to create a computational graph.
 pub struct Builder {
     nodes: Vec<Rc<Node>>,
-    constraints: Vec<(NodeId, NodeId)>,
+    constraints: Vec<Constraint>,
+    roms: Vec<Rc<[u32]>>,
+    custom_ops: Vec<(String, Operation)>,
+    output_groups: HashMap<String, Vec<NodeId>>,
     node_counter: NodeId,
+    fill_rounds: usize,
+    solve_constraints: bool,
+    strict_hints: bool,
+    solved_inputs: Vec<NodeId>,
+    mask: u32,
+    trace: RefCell<Vec<(NodeId, u32)>>,
+    #[cfg(feature = "record")]
+    log: Option<Vec<RecordedOp>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Builder {
@@ -46,135 +254,2526 @@ impl Builder {
         Self {
             nodes: Vec::new(),
             constraints: Vec::new(),
+            roms: Vec::new(),
+            custom_ops: Vec::new(),
+            output_groups: HashMap::new(),
             node_counter: 0,
+            fill_rounds: 0,
+            solve_constraints: false,
+            strict_hints: false,
+            solved_inputs: Vec::new(),
+            mask: u32::MAX,
+            trace: RefCell::new(Vec::new()),
+            #[cfg(feature = "record")]
+            log: None,
+        }
+    }
+
+    /// Creates a new builder with room for `n` nodes and `n` constraints
+    /// before it needs to reallocate.
+    pub fn with_capacity(n: usize) -> Self {
+        let mut builder = Self::new();
+        builder.nodes.reserve(n);
+        builder.constraints.reserve(n);
+        builder
+    }
+
+    /// Creates a new builder for arithmetic modulo `2^bits`: the result of
+    /// every operation and hint is truncated to its low `bits` bits, and
+    /// constraints compare the truncated values.
+    ///
+    /// Arithmetic wraps around instead of failing with
+    /// `EvalError::Overflow`, and an `overflowing_add` or `overflowing_mul`
+    /// counts as overflowed when its result doesn't fit in `bits` bits. Inputs
+    /// and constants are used as given. The bit width is kept by `to_bytes`,
+    /// but `to_r1cs` can't express it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is 0 or more than 32.
+    pub fn with_bit_width(bits: u32) -> Self {
+        assert!(
+            (1..=32).contains(&bits),
+            "Bit width must be between 1 and 32, got {}",
+            bits
+        );
+        let mut builder = Self::new();
+        builder.mask = u32::MAX >> (32 - bits);
+        builder
+    }
+
+    /// Returns the bit width set with `with_bit_width`, or 32.
+    pub fn bit_width(&self) -> u32 {
+        self.mask.count_ones()
+    }
+
+    /// Wraps `operation`, computing a node of the given kind, to truncate its
+    /// results to the bit width of the builder. Below 32 bits, arithmetic
+    /// kinds switch to wrapping operations first, since their results are
+    /// expected to overflow.
+    fn masked(&self, kind: Option<OpKind>, operation: Operation) -> Operation {
+        if self.mask == u32::MAX {
+            return operation;
         }
+        let operation = kind.and_then(OpKind::wrapping_operation).unwrap_or(operation);
+        let mask = self.mask;
+        Rc::new(move |values| operation(values).map(|value| value & mask))
+    }
+
+    fn node(&self, id: NodeId) -> Result<&Rc<Node>, BuilderError> {
+        self.nodes.get(id).ok_or(BuilderError::InvalidNode(id))
     }
 
     fn create_node(&mut self, value: Option<u32>, is_hint: bool, parents: Vec<NodeId>) -> NodeId {
-        let node = Rc::new(Node::new(value, is_hint, parents));
-        self.nodes.push(node);
+        self.push_node(Node::new(value, is_hint, parents))
+    }
+
+    fn push_node(&mut self, node: Node) -> NodeId {
+        self.nodes.push(Rc::new(node));
         self.node_counter += 1;
         self.node_counter - 1
     }
 
     /// Initializes a node in the graph.
     pub fn init(&mut self) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Init);
         self.create_node(None, false, Vec::new())
     }
 
     /// Initializes a node in a graph, set to a constant value.
     pub fn constant(&mut self, value: u32) -> NodeId {
-        self.create_node(Some(value), false, Vec::new())
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Constant(value));
+        let mut node = Node::new(Some(value), false, Vec::new());
+        node.is_constant = true;
+        self.push_node(node)
     }
 
-    /// Adds an operation between 2 nodes in the graph, returning a new node.
-    fn add_operation(&mut self, a: &NodeId, b: &NodeId, operation: Box<dyn Fn(u32, u32) -> u32>) -> NodeId {
-        let node_id = self.create_node(None, false, vec![*a, *b]);
-        *self.nodes[node_id].operation.borrow_mut() = Some(operation);
-        node_id
+    /// Adds an operation over some nodes in the graph, returning a new node.
+    fn add_operation(&mut self, kind: OpKind, parents: Vec<NodeId>, operation: Operation) -> NodeId {
+        let mut node = Node::new(None, false, parents);
+        node.kind = Some(kind);
+        *node.operation.borrow_mut() = Some(self.masked(Some(kind), operation));
+        self.push_node(node)
     }
 
     /// Adds 2 nodes in the graph, returning a new node.
+    ///
+    /// Computing the node fails with `EvalError::Overflow` if the sum
+    /// doesn't fit in a `u32`; see `overflowing_add` for a wrapping sum.
     pub fn add(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
-        self.add_operation(a, b, Box::new(|a, b| a + b))
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Add(*a, *b));
+        self.add_operation(
+            OpKind::Add,
+            vec![*a, *b],
+            Rc::new(|values| values[0].checked_add(values[1]).ok_or(EvalError::Overflow)),
+        )
+    }
+
+    /// Subtracts `b` from `a`, returning a new node.
+    ///
+    /// The result wraps around modulo 2^32 if `b` is larger than `a`, which
+    /// matches the arithmetic of `to_r1cs`.
+    pub fn sub(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Sub(*a, *b));
+        self.add_operation(
+            OpKind::Sub,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].wrapping_sub(values[1]))),
+        )
     }
 
     /// Multiplies 2 nodes in the graph, returning a new node.
+    ///
+    /// Computing the node fails with `EvalError::Overflow` if the product
+    /// doesn't fit in a `u32`; see `overflowing_mul` for a wrapping product.
     pub fn mul(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
-        self.add_operation(a, b, Box::new(|a, b| a * b))
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Mul(*a, *b));
+        self.add_operation(
+            OpKind::Mul,
+            vec![*a, *b],
+            Rc::new(|values| values[0].checked_mul(values[1]).ok_or(EvalError::Overflow)),
+        )
+    }
+
+    /// Initializes a constant node holding 1 for `true` and 0 for `false`.
+    pub fn constant_bool(&mut self, value: bool) -> NodeId {
+        self.constant(u32::from(value))
+    }
+
+    /// Takes the smaller of 2 nodes, returning a new node.
+    pub fn min(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Min(*a, *b));
+        self.add_operation(
+            OpKind::Min,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].min(values[1]))),
+        )
+    }
+
+    /// Takes the larger of 2 nodes, returning a new node.
+    pub fn max(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Max(*a, *b));
+        self.add_operation(
+            OpKind::Max,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].max(values[1]))),
+        )
+    }
+
+    /// Computes `a % m` for a modulus `m` known only when filling, returning
+    /// a new node.
+    ///
+    /// Filling fails with `EvalError::DivisionByZero` if `m` is 0.
+    pub fn rem_node(&mut self, a: &NodeId, m: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Rem(*a, *m));
+        self.add_operation(
+            OpKind::Rem,
+            vec![*a, *m],
+            Rc::new(|values| values[0].checked_rem(values[1]).ok_or(EvalError::DivisionByZero)),
+        )
+    }
+
+    /// Orders 2 nodes, returning `(min, max)`. This is the compare-and-swap
+    /// step that sorting networks are built from.
+    pub fn sort2(&mut self, a: &NodeId, b: &NodeId) -> (NodeId, NodeId) {
+        (self.min(a, b), self.max(a, b))
+    }
+
+    /// Computes `|a - b|` without underflowing, returning a new node.
+    pub fn abs_diff(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::AbsDiff(*a, *b));
+        self.add_operation(
+            OpKind::AbsDiff,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].abs_diff(values[1]))),
+        )
+    }
+
+    /// Restricts a node to the range `lo..=hi`, returning a new node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lo > hi`, like `u32::clamp`.
+    pub fn clamp(&mut self, a: &NodeId, lo: u32, hi: u32) -> NodeId {
+        assert!(lo <= hi, "clamp requires lo <= hi, got {} > {}", lo, hi);
+        let lo = self.constant(lo);
+        let hi = self.constant(hi);
+        let capped = self.min(a, &hi);
+        self.max(&capped, &lo)
+    }
+
+    /// Multiplies a node by a constant factor, returning a new node.
+    pub fn scale(&mut self, a: &NodeId, factor: u32) -> NodeId {
+        let factor = self.constant(factor);
+        self.mul(a, &factor)
+    }
+
+    /// Adds a constant to a node, returning a new node.
+    pub fn offset(&mut self, a: &NodeId, delta: u32) -> NodeId {
+        let delta = self.constant(delta);
+        self.add(a, &delta)
+    }
+
+    /// Evaluates the polynomial `coeffs[0] + coeffs[1] * x + coeffs[2] * x^2 + ...`
+    /// in Horner form, `(coeffs[n] * x + coeffs[n - 1]) * x + ...`, which
+    /// takes one multiplication per degree. Returns a constant 0 if `coeffs`
+    /// is empty.
+    pub fn horner(&mut self, x: &NodeId, coeffs: &[u32]) -> NodeId {
+        let Some((&leading, rest)) = coeffs.split_last() else {
+            return self.constant(0);
+        };
+        let mut acc = self.constant(leading);
+        for &coeff in rest.iter().rev() {
+            let product = self.mul(&acc, x);
+            acc = self.offset(&product, coeff);
+        }
+        acc
+    }
+
+    /// Returns nodes holding the running sums of `nodes`: output `i` is the
+    /// sum of `nodes[0..=i]`.
+    pub fn prefix_sum(&mut self, nodes: &[NodeId]) -> Vec<NodeId> {
+        let mut sums: Vec<NodeId> = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let sum = match sums.last() {
+                Some(previous) => self.add(previous, node),
+                None => *node,
+            };
+            sums.push(sum);
+        }
+        sums
+    }
+
+    /// Creates a read-only table holding `values`, to be read with
+    /// `rom_read`.
+    pub fn rom(&mut self, values: &[u32]) -> RomHandle {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Rom(values.to_vec()));
+        self.roms.push(values.into());
+        RomHandle(self.roms.len() - 1)
+    }
+
+    /// Registers a named operation that can then be added to the graph with
+    /// `apply`. Registering a name again replaces the operation for later
+    /// `apply` calls; nodes already created keep the old one.
+    pub fn register_op(&mut self, name: &str, f: impl Fn(&[u32]) -> u32 + 'static) {
+        let operation: Operation = Rc::new(move |values| Ok(f(values)));
+        match self.custom_ops.iter_mut().find(|(op_name, _)| op_name == name) {
+            Some((_, existing)) => *existing = operation,
+            None => self.custom_ops.push((name.to_string(), operation)),
+        }
+    }
+
+    /// Applies the operation registered as `name` to `parents`, returning a
+    /// new node.
+    ///
+    /// Returns `BuilderError::UnknownOp` if no operation is registered under
+    /// `name`.
+    pub fn apply(&mut self, name: &str, parents: &[NodeId]) -> Result<NodeId, BuilderError> {
+        let index = self
+            .custom_ops
+            .iter()
+            .position(|(op_name, _)| op_name == name)
+            .ok_or_else(|| BuilderError::UnknownOp(name.to_string()))?;
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Apply {
+            name: name.to_string(),
+            parents: parents.to_vec(),
+        });
+        let operation = Rc::clone(&self.custom_ops[index].1);
+        Ok(self.add_operation(OpKind::Custom(index), parents.to_vec(), operation))
+    }
+
+    /// Reads the entry of `rom` at the position held by `index`, returning a
+    /// new node.
+    ///
+    /// Returns `BuilderError::InvalidRom` if `rom` isn't a table of this
+    /// builder. Filling fails with `EvalError::IndexOutOfBounds` if `index`
+    /// is past the end of the table.
+    pub fn rom_read(&mut self, rom: &RomHandle, index: &NodeId) -> Result<NodeId, BuilderError> {
+        let table = Rc::clone(self.roms.get(rom.0).ok_or(BuilderError::InvalidRom(rom.0))?);
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::RomRead {
+            rom: rom.0,
+            index: *index,
+        });
+        Ok(self.add_operation(
+            OpKind::RomRead(*rom),
+            vec![*index],
+            Rc::new(move |values| {
+                table
+                    .get(values[0] as usize)
+                    .copied()
+                    .ok_or(EvalError::IndexOutOfBounds {
+                        index: values[0],
+                        len: table.len(),
+                    })
+            }),
+        ))
+    }
+
+    /// Selects `a` if `bit` is 1 and `b` if it is 0, computed as
+    /// `bit * a + (1 - bit) * b`.
+    ///
+    /// Also constrains `bit` with `assert_boolean`, so a non-boolean `bit`
+    /// fails `check_constraints`. The products and their sum wrap around,
+    /// like `overflowing_mul` and `overflowing_add`, so such a `bit` can't
+    /// make filling fail before the constraint is checked.
+    pub fn bool_select(&mut self, bit: &NodeId, a: &NodeId, b: &NodeId) -> NodeId {
+        self.assert_boolean(*bit);
+        let one = self.constant(1);
+        let not_bit = self.sub(&one, bit);
+        let picked_a = self.overflowing_mul(bit, a);
+        let picked_b = self.overflowing_mul(&not_bit, b);
+        self.overflowing_add(&picked_a, &picked_b)
+    }
+
+    /// Adds 2 nodes like `u32::overflowing_add`: the result wraps around on
+    /// overflow instead of failing, and the overflow is recorded in the result
+    /// node; see `overflowed`.
+    pub fn overflowing_add(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::OverflowingAdd(*a, *b));
+        self.add_operation(
+            OpKind::OverflowingAdd,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].wrapping_add(values[1]))),
+        )
+    }
+
+    /// Multiplies 2 nodes like `u32::overflowing_mul`: the result wraps
+    /// around on overflow instead of failing, and the overflow is recorded in
+    /// the result node; see `overflowed`.
+    pub fn overflowing_mul(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::OverflowingMul(*a, *b));
+        self.add_operation(
+            OpKind::OverflowingMul,
+            vec![*a, *b],
+            Rc::new(|values| Ok(values[0].wrapping_mul(values[1]))),
+        )
+    }
+
+    /// Returns `true` if the node was created by `overflowing_add` or
+    /// `overflowing_mul` and its last computed value overflowed.
+    pub fn overflowed(&self, id: NodeId) -> bool {
+        self.node(id).is_ok_and(|node| node.overflowed.get())
+    }
+
+    /// Combines the nodes with `op`, returning the node holding the result.
+    ///
+    /// The operations are arranged as a balanced tree rather than a chain, so
+    /// the result is only about `log2(nodes.len())` operations deep. Reducing
+    /// an empty slice returns a constant holding the identity of `op`.
+    ///
+    /// If every node is a constant, the result is folded at build time into
+    /// a single constant node instead, unless it would overflow. With a bit
+    /// width below 32, the folded value wraps like the operations would.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `op` is neither `OpKind::Add` nor `OpKind::Mul`, however
+    /// many nodes are given.
+    pub fn reduce(&mut self, nodes: &[NodeId], op: OpKind) -> NodeId {
+        let identity = match op {
+            OpKind::Add => 0,
+            OpKind::Mul => 1,
+            _ => panic!("Cannot reduce with {:?}", op),
+        };
+        if nodes.is_empty() {
+            return self.constant(identity);
+        }
+        if nodes.len() > 1 {
+            let constants: Option<Vec<u32>> = nodes
+                .iter()
+                .map(|&id| {
+                    let node = self.nodes.get(id).filter(|node| node.is_constant)?;
+                    *node.value.borrow()
+                })
+                .collect();
+            let wrapping = self.mask != u32::MAX;
+            let folded = constants.and_then(|values| match op {
+                OpKind::Add if wrapping => Some(values.into_iter().fold(0u32, u32::wrapping_add) & self.mask),
+                OpKind::Mul if wrapping => Some(values.into_iter().fold(1u32, u32::wrapping_mul) & self.mask),
+                OpKind::Add => values.into_iter().try_fold(0u32, u32::checked_add),
+                OpKind::Mul => values.into_iter().try_fold(1u32, u32::checked_mul),
+                _ => None,
+            });
+            if let Some(value) = folded {
+                for &id in nodes {
+                    self.fold_constant(id);
+                }
+                return self.constant(value);
+            }
+        }
+        let mut layer = nodes.to_vec();
+        while layer.len() > 1 {
+            layer = layer
+                .chunks(2)
+                .map(|pair| match (pair, op) {
+                    ([a, b], OpKind::Add) => self.add(a, b),
+                    ([a, b], _) => self.mul(a, b),
+                    _ => pair[0],
+                })
+                .collect();
+        }
+        layer[0]
     }
 
     /// Asserts that 2 nodes are equal.
     pub fn assert_equal(&mut self, a: NodeId, b: NodeId) {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::AssertEqual(a, b));
         self.constraints.push((a, b));
     }
 
-    fn fill_node(&self, node_id: NodeId) -> bool {
+    /// Asserts that a node is 0 or 1.
+    ///
+    /// This is the `x * (x - 1) == 0` gadget, written as `x * x == x` so it
+    /// needs a single multiplication. The square wraps around, like
+    /// `overflowing_mul`, so a large value fails the constraint instead of
+    /// failing to fill; modulo 2^32, `x * x == x` still only holds for 0 and 1.
+    pub fn assert_boolean(&mut self, id: NodeId) {
+        let squared = self.overflowing_mul(&id, &id);
+        self.assert_equal(squared, id);
+    }
+
+    /// Asserts that all the nodes are equal, by constraining each of them to
+    /// equal the first.
+    pub fn assert_all_equal(&mut self, nodes: &[NodeId]) {
+        if let Some((&first, rest)) = nodes.split_first() {
+            for &node in rest {
+                self.assert_equal(first, node);
+            }
+        }
+    }
+
+    /// Asserts that `b` is exactly `delta` more than `a`, modulo 2^32.
+    ///
+    /// This adds a node holding `a + delta`, wrapping around like
+    /// `overflowing_add`, and asserts that `b` equals it, so a failing check
+    /// reports `b` on the left and the `a + delta` node on the right. Like
+    /// every constraint in `to_r1cs`, the relation only holds modulo 2^32: a
+    /// `b` that is smaller than `a` satisfies it too if `a + delta` wraps
+    /// around to `b`, so it doesn't on its own show that `b` is the larger of
+    /// the two.
+    pub fn assert_diff(&mut self, a: NodeId, b: NodeId, delta: u32) {
+        let delta = self.constant(delta);
+        let expected = self.overflowing_add(&a, &delta);
+        self.assert_equal(b, expected);
+    }
+
+    /// Asserts that `a == b` whenever `flag` is nonzero; when `flag` is 0 the
+    /// values of `a` and `b` are unconstrained.
+    ///
+    /// This is an is-zero gadget: a hint holds `nonzero`, 1 if `flag` is
+    /// nonzero and 0 otherwise, constrained with `assert_boolean` and
+    /// `flag * (1 - nonzero) == 0`, so it must be 1 for any nonzero `flag`.
+    /// The field version of the gadget hints an inverse of `flag` instead,
+    /// but modulo 2^32 even values have none. `nonzero * (a - b) == 0` then
+    /// enforces the equality. Every part is an ordinary constraint, so it is
+    /// checked by every constraint check and by `to_r1cs`.
+    pub fn assert_equal_if(&mut self, flag: NodeId, a: NodeId, b: NodeId) {
+        let nonzero = self.hint(|values| u32::from(values[0] != 0), vec![flag]);
+        self.assert_boolean(nonzero);
+        let one = self.constant(1);
+        let zero = self.constant(0);
+        let is_zero = self.sub(&one, &nonzero);
+        let flag_if_zero = self.overflowing_mul(&flag, &is_zero);
+        self.assert_equal(flag_if_zero, zero);
+        let difference = self.sub(&a, &b);
+        let guarded = self.overflowing_mul(&nonzero, &difference);
+        self.assert_equal(guarded, zero);
+    }
+
+    fn fill_node(&self, node_id: NodeId) -> Result<bool, BuilderError> {
         let node = &self.nodes[node_id];
         if node.value.borrow().is_none() {
             let parent_values: Vec<Option<u32>> = node
                 .parents
                 .iter()
-                .map(|&id| *self.nodes[id].value.borrow())
+                .map(|&id| self.nodes.get(id).and_then(|parent| *parent.value.borrow()))
                 .collect();
 
             if parent_values.iter().all(|v| v.is_some()) {
                 let parent_values: Vec<u32> = parent_values.into_iter().map(|v| v.unwrap()).collect();
                 if let Some(operation) = &*node.operation.borrow() {
-                    let result = match parent_values.len() {
-                        2 => operation(parent_values[0], parent_values[1]),
-                        1 => operation(parent_values[0], parent_values[0]),
-                        _ => panic!("Unsupported number of parent values"),
-                    };
+                    node.evaluations.set(node.evaluations.get() + 1);
+                    let result = self
+                        .run_operation(node, operation, &parent_values)
+                        .map_err(|err| BuilderError::Eval(node_id, err))?;
+                    if cfg!(debug_assertions) && node.is_hint {
+                        let second = self
+                            .run_operation(node, operation, &parent_values)
+                            .map_err(|err| BuilderError::Eval(node_id, err))?;
+                        if second != result {
+                            let err = EvalError::NondeterministicHint { first: result, second };
+                            return Err(BuilderError::Eval(node_id, err));
+                        }
+                    }
+                    if let Some(kind) = node.kind {
+                        node.overflowed.set(kind.overflows(&parent_values, self.mask));
+                    }
                     *node.value.borrow_mut() = Some(result);
-                    println!("Filling node {} with value {}", node_id, result);
-                    return true;
+                    self.trace.borrow_mut().push((node_id, result));
+                    return Ok(true);
                 }
             }
         }
-        false
+        Ok(false)
     }
 
     /// Fills in all the nodes of the graph based on some inputs.
-    pub fn fill_nodes(&mut self, inputs: Vec<Option<u32>>) {
-        for (node_id, value) in inputs.iter().enumerate() {
-            if let Some(value) = value {
-                println!("Setting input node {} to value {}", node_id, value);
-                *self.nodes[node_id].value.borrow_mut() = Some(*value);
+    ///
+    /// `inputs` is indexed by node id, so every node created before the last
+    /// input needs a slot (`None` for non-inputs). Inserting a node earlier in
+    /// the build shifts those positions; prefer `fill_inputs`, which addresses
+    /// inputs by the handles returned from `init`.
+    ///
+    /// Returns an error, without filling anything, if `inputs` is longer than
+    /// the graph or has a value at the position of a non-input node.
+    #[deprecated(note = "use fill_inputs")]
+    pub fn fill_nodes(&mut self, inputs: Vec<Option<u32>>) -> Result<(), BuilderError> {
+        if inputs.len() > self.nodes.len() {
+            return Err(BuilderError::TooManyInputs {
+                given: inputs.len(),
+                nodes: self.nodes.len(),
+            });
+        }
+        let inputs: Vec<(NodeId, u32)> = inputs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(node_id, value)| Some((node_id, value?)))
+            .collect();
+        self.fill_inputs(&inputs)
+    }
+
+    /// Fills in all the nodes of the graph, given the values of the input
+    /// nodes as `(handle, value)` pairs.
+    ///
+    /// Values computed by an earlier fill are cleared first, so filling again
+    /// recomputes every operation from the new inputs. Inputs that aren't
+    /// given keep their previous value.
+    ///
+    /// Returns an error, without filling anything, if a handle doesn't refer
+    /// to an input node or the graph fails `validate`.
+    pub fn fill_inputs(&mut self, inputs: &[(NodeId, u32)]) -> Result<(), BuilderError> {
+        self.validate()?;
+        self.set_inputs(inputs)?;
+        self.fill_rounds = self.propagate()?;
+        if self.solve_constraints {
+            loop {
+                let solved = self.solve_step()?;
+                if solved.is_empty() {
+                    break;
+                }
+                self.solved_inputs.extend(solved);
+                self.fill_rounds += self.propagate()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the values of input nodes, given as `(handle, value)` pairs,
+    /// without computing anything else. Other nodes can then be computed on
+    /// demand with `value`.
+    ///
+    /// Values computed earlier are cleared, like in `fill_inputs`. Returns an
+    /// error, without setting anything, if a handle doesn't refer to an input
+    /// node.
+    pub fn set_inputs(&mut self, inputs: &[(NodeId, u32)]) -> Result<(), BuilderError> {
+        for &(node_id, _) in inputs {
+            if !self.node(node_id)?.is_input() {
+                return Err(BuilderError::NotAnInput(node_id));
+            }
+        }
+        self.clear_computed();
+        for node_id in std::mem::take(&mut self.solved_inputs) {
+            *self.nodes[node_id].value.borrow_mut() = None;
+        }
+        for &(node_id, value) in inputs {
+            self.set_input(node_id, value);
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a node, computing it and any unfilled nodes it
+    /// depends on if needed. Computed values are stored in the nodes, as if
+    /// filled.
+    ///
+    /// Returns `None` if the node doesn't exist, depends on an input without
+    /// a value or on a cycle, or if an operation fails.
+    pub fn value(&self, id: NodeId) -> Option<u32> {
+        self.compute(id)
+    }
+
+    /// Runs the operation of `node` on its parent values, or its function if
+    /// it is a dynamic hint.
+    fn run_operation(&self, node: &Node, operation: &Operation, parent_values: &[u32]) -> Result<u32, EvalError> {
+        match &node.dynamic {
+            Some(value_func) => Ok(value_func(self) & self.mask),
+            None => operation(parent_values),
+        }
+    }
+
+    /// Computes `root` and the unfilled nodes it depends on, walking the
+    /// dependencies with an explicit stack so deep graphs can't overflow the
+    /// call stack.
+    fn compute(&self, root: NodeId) -> Option<u32> {
+        if let Some(value) = *self.nodes.get(root)?.value.borrow() {
+            return Some(value);
+        }
+        let mut visiting = vec![false; self.nodes.len()];
+        visiting[root] = true;
+        // Each entry is a node being computed and the index of the next
+        // parent to look at.
+        let mut stack = vec![(root, 0)];
+        while let Some(&(node_id, next)) = stack.last() {
+            let node = &self.nodes[node_id];
+            if let Some(&parent) = node.parents.get(next) {
+                if self.nodes.get(parent)?.value.borrow().is_none() {
+                    if visiting[parent] {
+                        return None;
+                    }
+                    visiting[parent] = true;
+                    stack.push((parent, 0));
+                    continue;
+                }
+                stack.last_mut().expect("The stack isn't empty").1 += 1;
+                continue;
+            }
+            stack.pop();
+            visiting[node_id] = false;
+            let parent_values: Vec<u32> = node
+                .parents
+                .iter()
+                .map(|&id| self.nodes[id].value.borrow().expect("Parents are computed first"))
+                .collect();
+            let operation = node.operation.borrow().clone()?;
+            node.evaluations.set(node.evaluations.get() + 1);
+            let result = self.run_operation(node, &operation, &parent_values).ok()?;
+            if let Some(kind) = node.kind {
+                node.overflowed.set(kind.overflows(&parent_values, self.mask));
+            }
+            *node.value.borrow_mut() = Some(result);
+            self.trace.borrow_mut().push((node_id, result));
+            if let Some(frame) = stack.last_mut() {
+                frame.1 += 1;
+            }
+        }
+        *self.nodes[root].value.borrow()
+    }
+
+    /// Enables or disables solving for inputs through constraints while
+    /// filling.
+    ///
+    /// When enabled, an `assert_equal` between a filled node and an input
+    /// that wasn't given fills the input with the same value, and filling
+    /// continues from there. Filling then fails with
+    /// `BuilderError::Constraint` if both sides of a constraint are known
+    /// and differ. Inputs filled this way are cleared again by the next fill.
+    pub fn solve_constraints(&mut self, enabled: bool) {
+        self.solve_constraints = enabled;
+    }
+
+    /// Fills unfilled inputs that are constrained to equal a filled node,
+    /// returning the ones it filled.
+    fn solve_step(&self) -> Result<Vec<NodeId>, BuilderError> {
+        let mut solved = Vec::new();
+        for (index, &(left, right)) in self.constraints.iter().enumerate() {
+            let left_value = *self.node(left)?.value.borrow();
+            let right_value = *self.node(right)?.value.borrow();
+            match (left_value, right_value) {
+                (Some(value), None) if self.nodes[right].is_input() => {
+                    self.set_input(right, value);
+                    solved.push(right);
+                }
+                (None, Some(value)) if self.nodes[left].is_input() => {
+                    self.set_input(left, value);
+                    solved.push(left);
+                }
+                (Some(_), Some(_)) if !self.constraint_holds(&(left, right)) => {
+                    return Err(ConstraintError {
+                        index,
+                        left,
+                        right,
+                        left_value,
+                        right_value,
+                    }
+                    .into())
+                }
+                _ => {}
+            }
+        }
+        Ok(solved)
+    }
+
+    /// Returns how many sweeps over the nodes the last fill needed before
+    /// no more nodes could be filled, not counting the final sweep.
+    ///
+    /// Nodes are swept in id order, so this is 1 when every node comes after
+    /// its parents, and grows with the length of chains of hints that depend
+    /// on later nodes.
+    pub fn last_fill_rounds(&self) -> usize {
+        self.fill_rounds
+    }
+
+    /// Like `fill_inputs`, but takes the `(handle, value)` pairs from any
+    /// iterator.
+    pub fn fill_nodes_from<I>(&mut self, pairs: I) -> Result<(), BuilderError>
+    where
+        I: IntoIterator<Item = (NodeId, u32)>,
+    {
+        let inputs: Vec<(NodeId, u32)> = pairs.into_iter().collect();
+        self.fill_inputs(&inputs)
+    }
+
+    /// Clears the value of every node except constants and the nodes in
+    /// `keep`, so inputs that stay fixed across fills don't need to be given
+    /// again.
+    pub fn reset_values_except(&mut self, keep: &[NodeId]) {
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            if !node.is_constant && !keep.contains(&node_id) {
+                *node.value.borrow_mut() = None;
+                node.overflowed.set(false);
+            }
+        }
+        self.solved_inputs.retain(|id| !keep.contains(id));
+    }
+
+    /// Returns how many times each node's operation has run since the last
+    /// fill started, indexed by node id. Inputs and constants are always 0.
+    ///
+    /// The extra run used to check hints for determinism in debug builds
+    /// isn't counted.
+    pub fn eval_counts(&self) -> Vec<usize> {
+        self.nodes.iter().map(|node| node.evaluations.get()).collect()
+    }
+
+    /// Saves the value of every node, to be put back with `restore_state`.
+    pub fn save_state(&self) -> GraphState {
+        GraphState {
+            values: self
+                .nodes
+                .iter()
+                .map(|node| (*node.value.borrow(), node.overflowed.get()))
+                .collect(),
+            solved_inputs: self.solved_inputs.clone(),
+        }
+    }
+
+    /// Puts back the node values saved in `state`, including constants
+    /// changed since with `set_constant`. Nodes created after the state was
+    /// saved are left unfilled, and constants among them keep their value.
+    pub fn restore_state(&mut self, state: &GraphState) {
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            if node.is_constant && node_id >= state.values.len() {
+                continue;
+            }
+            let (value, overflowed) = state.values.get(node_id).copied().unwrap_or_default();
+            *node.value.borrow_mut() = value;
+            node.overflowed.set(overflowed);
+        }
+        self.solved_inputs = state.solved_inputs.clone();
+    }
+
+    /// Forgets the values of all operation nodes, leaving inputs and
+    /// constants as they are.
+    fn clear_computed(&self) {
+        self.trace.borrow_mut().clear();
+        for node in &self.nodes {
+            if node.operation.borrow().is_some() {
+                *node.value.borrow_mut() = None;
+                node.overflowed.set(false);
+                node.evaluations.set(0);
             }
         }
+    }
+
+    fn set_input(&self, node_id: NodeId, value: u32) {
+        *self.nodes[node_id].value.borrow_mut() = Some(value);
+        self.trace.borrow_mut().push((node_id, value));
+    }
+
+    /// Returns the `(node, value)` pairs set or computed since the last fill
+    /// started, in the order they happened: first the given inputs, then
+    /// each node as it was computed.
+    ///
+    /// Comparing the traces of two runs shows where their values first
+    /// diverge.
+    pub fn trace(&self) -> Vec<(NodeId, u32)> {
+        self.trace.borrow().clone()
+    }
+
+    /// Fills the graph from `inputs` like `fill_inputs`, then collects the
+    /// output values and checks the constraints.
+    ///
+    /// An error while filling, such as an `add` or `mul` that overflows, is
+    /// reported in the result along with whatever values were computed
+    /// before it, and the constraints then count as not satisfied. Values
+    /// from an earlier fill are cleared even if the inputs are rejected.
+    pub fn run(&mut self, inputs: &[(NodeId, u32)]) -> RunResult {
+        self.clear_computed();
+        let error = self.fill_inputs(inputs).err();
+        let consumers = self.consumer_counts();
+        RunResult {
+            outputs: (0..self.nodes.len())
+                .filter(|&id| consumers[id] == 0)
+                .map(|id| (id, *self.nodes[id].value.borrow()))
+                .collect(),
+            satisfied: error.is_none() && self.is_satisfied(),
+            error,
+        }
+    }
 
+    /// Fills nodes until no more can be filled, returning how many sweeps
+    /// filled at least one node.
+    fn propagate(&self) -> Result<usize, BuilderError> {
+        let mut rounds = 0;
         loop {
             let mut filled_any = false;
             for node_id in 0..self.nodes.len() {
-                if self.fill_node(node_id) {
+                if self.fill_node(node_id)? {
                     filled_any = true;
                 }
             }
             if !filled_any {
-                break;
+                return Ok(rounds);
             }
+            rounds += 1;
         }
     }
 
-    /// Given a graph that has `fill_nodes` already called on it
-    /// checks that all the constraints hold.
-    pub fn check_constraints(&self) -> bool {
-        for (a, b) in &self.constraints {
-            let a_value = self.nodes[*a].value.borrow();
-            let b_value = self.nodes[*b].value.borrow();
-            println!(
-                "Checking constraint: node {} value {} == node {} value {}",
-                a,
-                a_value.unwrap(),
-                b,
-                b_value.unwrap()
-            );
-            if *a_value != *b_value {
-                return false;
+    /// Evaluates the graph into a separate buffer, without touching the
+    /// values stored in the nodes.
+    ///
+    /// `inputs` are `(handle, value)` pairs like in `fill_inputs`; values
+    /// given for nodes that aren't inputs are ignored. The result is indexed
+    /// by node id. Nodes that can't be computed, including ones whose
+    /// operation fails, are `None` in the result.
+    pub fn evaluate_into(&self, inputs: &[(NodeId, u32)]) -> Vec<Option<u32>> {
+        let mut values = Vec::with_capacity(self.nodes.len());
+        self.evaluate_reuse(inputs, &mut values);
+        values
+    }
+
+    /// Like `evaluate_into`, but writes the values into `buffer`, replacing
+    /// its contents. Reusing one buffer across many evaluations avoids
+    /// allocating a new one each time.
+    pub fn evaluate_reuse(&self, inputs: &[(NodeId, u32)], buffer: &mut Vec<Option<u32>>) {
+        let values = buffer;
+        values.clear();
+        values.resize(self.nodes.len(), None);
+        self.place_inputs(inputs, values);
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            if node.is_constant {
+                values[node_id] = *node.value.borrow();
+            } else if let Some(operation) = &*node.operation.borrow() {
+                let parent_values: Option<Vec<u32>> = node.parents.iter().map(|&id| values[id]).collect();
+                values[node_id] = parent_values.and_then(|parent_values| operation(&parent_values).ok());
             }
         }
-        true
     }
 
-    /// An API for hinting values that allows you to perform operations
-    /// like division or computing square roots.
-    pub fn hint<F>(&mut self, value_func: F, depends_on: Vec<NodeId>) -> NodeId
-    where
-        F: 'static + Fn(&[u32]) -> u32,
-    {
-        let node_id = self.create_node(None, true, depends_on.clone());
-        let nodes = self.nodes.clone();
+    /// Writes the given input values into `values`, indexed by node id,
+    /// skipping pairs that don't refer to an input node.
+    fn place_inputs<T: Copy>(&self, inputs: &[(NodeId, T)], values: &mut [Option<T>]) {
+        for &(node_id, value) in inputs {
+            if self.nodes.get(node_id).is_some_and(|node| node.is_input()) {
+                values[node_id] = Some(value);
+            }
+        }
+    }
+
+    /// Evaluates `N` independent sets of inputs in lockstep, applying every
+    /// operation lane-wise. Lane `i` of the result is what `evaluate_into`
+    /// returns for lane `i` of the inputs.
+    ///
+    /// `inputs` are `(handle, lanes)` pairs like in `evaluate_into`. A node
+    /// is `None` if it can't be computed in every lane.
+    pub fn evaluate_lanes<const N: usize>(&self, inputs: &[(NodeId, [u32; N])]) -> Vec<Option<[u32; N]>> {
+        let mut values: Vec<Option<[u32; N]>> = vec![None; self.nodes.len()];
+        self.place_inputs(inputs, &mut values);
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            if node.is_constant {
+                values[node_id] = node.value.borrow().map(|value| [value; N]);
+            } else if let Some(operation) = &*node.operation.borrow() {
+                let parent_values: Option<Vec<[u32; N]>> = node.parents.iter().map(|&id| values[id]).collect();
+                values[node_id] = parent_values.and_then(|parent_values| {
+                    let mut lanes = [0; N];
+                    for (lane, result) in lanes.iter_mut().enumerate() {
+                        let lane_values: Vec<u32> = parent_values.iter().map(|values| values[lane]).collect();
+                        *result = operation(&lane_values).ok()?;
+                    }
+                    Some(lanes)
+                });
+            }
+        }
+        values
+    }
+
+    /// Compiles the graph into a standalone function from input values to
+    /// the value of every node, in id order.
+    ///
+    /// The function takes `(handle, value)` pairs like `evaluate_into`,
+    /// ignoring pairs that don't refer to an input node. It doesn't touch the
+    /// builder, so it can be called any number of times.
+    ///
+    /// # Panics
+    ///
+    /// The returned function panics if an input isn't given a value, if an
+    /// operation fails, or if the graph has a cycle.
+    pub fn compile(&self) -> impl Fn(&[(NodeId, u32)]) -> Vec<u32> {
+        enum Step {
+            Constant(u32),
+            Input,
+            Operation(Vec<NodeId>, Operation),
+        }
+
+        let mut steps = Vec::with_capacity(self.nodes.len());
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            let step = if node.is_constant {
+                Step::Constant(node.value.borrow().expect("Constants always have a value"))
+            } else if let Some(operation) = &*node.operation.borrow() {
+                Step::Operation(node.parents.clone(), Rc::clone(operation))
+            } else {
+                Step::Input
+            };
+            steps.push((node_id, step));
+        }
+        let node_count = self.nodes.len();
+
+        move |inputs: &[(NodeId, u32)]| {
+            let mut given: Vec<Option<u32>> = vec![None; node_count];
+            for &(node_id, value) in inputs {
+                if let Some(slot) = given.get_mut(node_id) {
+                    *slot = Some(value);
+                }
+            }
+            let mut values: Vec<Option<u32>> = vec![None; node_count];
+            for (node_id, step) in &steps {
+                values[*node_id] = Some(match step {
+                    Step::Constant(value) => *value,
+                    Step::Input => given[*node_id].unwrap_or_else(|| panic!("Input {} has no value", node_id)),
+                    Step::Operation(parents, operation) => {
+                        let parent_values: Vec<u32> = parents.iter().map(|&id| values[id].unwrap()).collect();
+                        operation(&parent_values)
+                            .unwrap_or_else(|err| panic!("Failed to evaluate node {}: {}", node_id, err))
+                    }
+                });
+            }
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(node_id, value)| value.unwrap_or_else(|| panic!("Node {} lies on a cycle", node_id)))
+                .collect()
+        }
+    }
+
+    /// Evaluates only what `sinks` depend on and returns their values, in
+    /// the order given.
+    ///
+    /// `inputs` are `(handle, value)` pairs like in `evaluate_into`. Nothing
+    /// is written to the builder: each intermediate value is dropped as soon
+    /// as its last consumer has read it, so peak memory follows the width of
+    /// the graph rather than its size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a sink depends on an input without a value, if an operation
+    /// fails, or if a sink doesn't exist or lies on a cycle.
+    pub fn evaluate_streaming(&self, inputs: &[(NodeId, u32)], sinks: &[NodeId]) -> Vec<u32> {
+        self.stream(inputs, sinks, &mut 0)
+    }
+
+    /// Does the work of `evaluate_streaming`, recording the largest number
+    /// of values held at once in `peak`.
+    fn stream(&self, inputs: &[(NodeId, u32)], sinks: &[NodeId], peak: &mut usize) -> Vec<u32> {
+        let needed = self.dependency_closure(sinks);
+        let mut pending = vec![0usize; self.nodes.len()];
+        let mut pinned = vec![false; self.nodes.len()];
+        let mut input_values = vec![None; self.nodes.len()];
+        self.place_inputs(inputs, &mut input_values);
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            if needed[node_id] {
+                for &parent in &node.parents {
+                    pending[parent] += 1;
+                }
+            }
+        }
+        for &sink in sinks {
+            if let Some(pinned) = pinned.get_mut(sink) {
+                *pinned = true;
+            }
+        }
+
+        let mut live: HashMap<NodeId, u32> = HashMap::new();
+        for node_id in self.topological_order().into_iter().filter(|&id| needed[id]) {
+            let node = &self.nodes[node_id];
+            let value = if node.is_constant {
+                node.value.borrow().expect("Constants always have a value")
+            } else if let Some(operation) = &*node.operation.borrow() {
+                let parent_values: Vec<u32> = node.parents.iter().map(|id| live[id]).collect();
+                operation(&parent_values).unwrap_or_else(|err| panic!("Failed to evaluate node {}: {}", node_id, err))
+            } else {
+                input_values[node_id].unwrap_or_else(|| panic!("No value given for input node {}", node_id))
+            };
+            for &parent in &node.parents {
+                pending[parent] -= 1;
+                if pending[parent] == 0 && !pinned[parent] {
+                    live.remove(&parent);
+                }
+            }
+            live.insert(node_id, value);
+            *peak = (*peak).max(live.len());
+        }
+        sinks
+            .iter()
+            .map(|sink| {
+                *live
+                    .get(sink)
+                    .unwrap_or_else(|| panic!("Node {} doesn't exist or lies on a cycle", sink))
+            })
+            .collect()
+    }
+
+    /// Assigns every node a buffer slot for evaluation in dependency order,
+    /// such that nodes whose values are needed at the same time never
+    /// share a slot. Slots are numbered from 0 and reused as soon as
+    /// possible, so the number of distinct slots follows the width of the
+    /// graph rather than its size.
+    ///
+    /// A node's value is needed until its last consumer is computed, which
+    /// may write into the same slot. Nodes without consumers, and nodes in
+    /// constraints or output groups, keep their slot to the end. Nodes on a
+    /// cycle each get a slot of their own.
+    pub fn buffer_assignment(&self) -> Vec<usize> {
+        // Evaluate depth-first rather than in `topological_order`, which
+        // starts with every input and constant and keeps them all live.
+        let mut acyclic = vec![false; self.nodes.len()];
+        for node_id in self.topological_order() {
+            acyclic[node_id] = true;
+        }
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.nodes.len()];
+        for root in (0..self.nodes.len()).filter(|&id| acyclic[id]) {
+            let mut stack = vec![(root, false)];
+            while let Some((node_id, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(node_id);
+                } else if !visited[node_id] {
+                    visited[node_id] = true;
+                    stack.push((node_id, true));
+                    stack.extend(self.nodes[node_id].parents.iter().rev().map(|&parent| (parent, false)));
+                }
+            }
+        }
+
+        let mut last_use: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        for (position, &node_id) in order.iter().enumerate() {
+            for &parent in &self.nodes[node_id].parents {
+                last_use[parent] = Some(position);
+            }
+        }
+        for id in self
+            .constrained_nodes()
+            .into_iter()
+            .chain(self.output_groups.values().flatten().copied())
         {
-            let mut operation = self.nodes[node_id].operation.borrow_mut();
-            *operation = Some(Box::new(move |_, _| {
-                let parent_values: Vec<u32> = depends_on
-                    .iter()
-                    .map(|&id| nodes[id].value.borrow().expect("Parent value should be filled"))
-                    .collect();
-                value_func(&parent_values)
-            }));
+            if let Some(last_use) = last_use.get_mut(id) {
+                *last_use = None;
+            }
         }
-        node_id
+
+        let mut slots = vec![usize::MAX; self.nodes.len()];
+        let mut free: Vec<usize> = Vec::new();
+        let mut slot_count = 0;
+        for (position, &node_id) in order.iter().enumerate() {
+            for &parent in &self.nodes[node_id].parents {
+                if last_use[parent] == Some(position) && !free.contains(&slots[parent]) {
+                    free.push(slots[parent]);
+                }
+            }
+            slots[node_id] = free.pop().unwrap_or_else(|| {
+                slot_count += 1;
+                slot_count - 1
+            });
+        }
+        for slot in slots.iter_mut().filter(|slot| **slot == usize::MAX) {
+            *slot = slot_count;
+            slot_count += 1;
+        }
+        slots
+    }
+
+    /// Marks nodes as outputs under the group `name`, adding to the group if
+    /// it already exists.
+    pub fn add_output_group(&mut self, name: &str, nodes: &[NodeId]) -> Result<(), BuilderError> {
+        for &id in nodes {
+            self.node(id)?;
+        }
+        self.output_groups.entry(name.to_string()).or_default().extend(nodes);
+        Ok(())
+    }
+
+    /// Returns the nodes in the output group `name`, in the order they were
+    /// added.
+    pub fn outputs_of(&self, name: &str) -> Option<&[NodeId]> {
+        self.output_groups.get(name).map(Vec::as_slice)
+    }
+
+    /// Finishes building, returning a `Graph` that can only be evaluated and
+    /// queried.
+    pub fn build(self) -> Graph {
+        Graph::new(self)
+    }
+
+    /// Given a graph that has `fill_inputs` already called on it
+    /// checks that all the constraints hold.
+    pub fn check_constraints(&self) -> bool {
+        self.constraints.iter().all(|constraint| self.constraint_holds(constraint))
+    }
+
+    /// Returns `true` if every constraint holds on the current values.
+    ///
+    /// Like `check_constraints`, a constraint on an unfilled node counts as
+    /// not holding even if the other side is unfilled too.
+    pub fn is_satisfied(&self) -> bool {
+        self.check_constraints()
+    }
+
+    /// Returns `true` if both sides of a constraint exist, are filled and
+    /// have the same value.
+    fn constraint_holds(&self, &(a, b): &Constraint) -> bool {
+        let value = |id: NodeId| self.node(id).ok().and_then(|node| *node.value.borrow());
+        value(a).is_some() && value(a) == value(b)
+    }
+
+    /// Sums `|a - b|` over the constraints, as a measure of how far the
+    /// current values are from satisfying them: 0 if every constraint holds.
+    ///
+    /// A constraint on an unfilled or missing node adds `u32::MAX`.
+    pub fn constraint_penalty(&self) -> u64 {
+        let value = |id: NodeId| self.node(id).ok().and_then(|node| *node.value.borrow());
+        self.constraints
+            .iter()
+            .filter(|constraint| !self.constraint_holds(constraint))
+            .map(|&(a, b)| match (value(a), value(b)) {
+                (Some(a), Some(b)) => u64::from(a.abs_diff(b)),
+                _ => u64::from(u32::MAX),
+            })
+            .sum()
+    }
+
+    /// Returns a copy of the constraints, in the order they were added.
+    pub fn export_constraints(&self) -> Vec<Constraint> {
+        self.constraints.clone()
+    }
+
+    /// Replaces the constraints with `c`, for swapping constraint sets on a
+    /// graph with a fixed structure. A constraint on a node that doesn't
+    /// exist never holds.
+    pub fn import_constraints(&mut self, c: Vec<Constraint>) {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::ImportConstraints(c.clone()));
+        self.constraints = c;
+    }
+
+    /// Returns the constraints that have `id` on either side, in the order
+    /// they were added.
+    pub fn constraints_for(&self, id: NodeId) -> Vec<&Constraint> {
+        self.constraints.iter().filter(|&&(a, b)| a == id || b == id).collect()
+    }
+
+    /// Returns every node feeding into either side of the constraint at
+    /// `index`, with its current value, in id order.
+    ///
+    /// Returns `None` if there is no constraint at `index`.
+    pub fn trace_constraint(&self, index: usize) -> Option<Vec<(NodeId, Option<u32>)>> {
+        let &(a, b) = self.constraints.get(index)?;
+        let reached = self.dependency_closure(&[a, b]);
+        Some(
+            (0..self.nodes.len())
+                .filter(|&id| reached[id])
+                .map(|id| (id, *self.nodes[id].value.borrow()))
+                .collect(),
+        )
+    }
+
+    /// Returns the indices of the failing constraints that share a root
+    /// cause with the first failing one, in order.
+    ///
+    /// A constraint fails like in `is_satisfied`. Failing constraints are
+    /// grouped when they depend on a common input, directly or through other
+    /// failing constraints in the group, so an unrelated failure elsewhere in
+    /// the graph isn't included. Returns an empty list if every constraint
+    /// holds.
+    pub fn minimal_failing_set(&self) -> Vec<usize> {
+        let failing: Vec<(usize, Vec<bool>)> = self
+            .constraints
+            .iter()
+            .enumerate()
+            .filter(|&(_, constraint)| !self.constraint_holds(constraint))
+            .map(|(index, &(a, b))| {
+                let mut inputs = self.dependency_closure(&[a, b]);
+                for (node_id, reached) in inputs.iter_mut().enumerate() {
+                    *reached &= self.nodes[node_id].is_input();
+                }
+                (index, inputs)
+            })
+            .collect();
+        let Some((_, first_inputs)) = failing.first() else {
+            return Vec::new();
+        };
+
+        let mut group_inputs = first_inputs.clone();
+        let mut in_group = vec![false; failing.len()];
+        in_group[0] = true;
+        let mut grew = true;
+        while grew {
+            grew = false;
+            for (position, (_, inputs)) in failing.iter().enumerate() {
+                let overlaps = inputs.iter().zip(&group_inputs).any(|(&a, &b)| a && b);
+                if !in_group[position] && overlaps {
+                    in_group[position] = true;
+                    for (grouped, &input) in group_inputs.iter_mut().zip(inputs) {
+                        *grouped |= input;
+                    }
+                    grew = true;
+                }
+            }
+        }
+        failing
+            .iter()
+            .zip(in_group)
+            .filter(|&(_, grouped)| grouped)
+            .map(|(&(index, _), _)| index)
+            .collect()
+    }
+
+    /// Fills in the graph like `fill_inputs` and then checks the constraints,
+    /// returning the first one that doesn't hold.
+    pub fn fill_and_check(&mut self, inputs: &[(NodeId, u32)]) -> Result<(), BuilderError> {
+        self.fill_inputs(inputs)?;
+        for (index, &(left, right)) in self.constraints.iter().enumerate() {
+            if !self.constraint_holds(&(left, right)) {
+                let left_value = *self.node(left)?.value.borrow();
+                let right_value = *self.node(right)?.value.borrow();
+                return Err(ConstraintError {
+                    index,
+                    left,
+                    right,
+                    left_value,
+                    right_value,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a random value for every input node, as `(handle, value)`
+    /// pairs in id order, ready to pass to `fill_inputs`.
+    #[cfg(feature = "rand")]
+    pub fn random_inputs(&self, rng: &mut impl rand::Rng) -> Vec<(NodeId, u32)> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].is_input())
+            .map(|id| (id, rng.gen()))
+            .collect()
+    }
+
+    /// Checks the graph end to end: validates its structure, makes sure every
+    /// input that a constraint depends on has a value, fills the graph and
+    /// checks the constraints. Returns the first error from any of these.
+    pub fn verify(&mut self, inputs: &[(NodeId, u32)]) -> Result<(), BuilderError> {
+        self.validate()?;
+        let needed = self.dependency_closure(&self.constrained_nodes());
+        if let Some(missing) = (0..self.nodes.len())
+            .find(|&id| needed[id] && self.nodes[id].is_input() && !inputs.iter().any(|&(input, _)| input == id))
+        {
+            return Err(BuilderError::MissingInput(missing));
+        }
+        self.fill_and_check(inputs)
+    }
+
+    /// An API for hinting values that allows you to perform operations
+    /// like division or computing square roots.
+    ///
+    /// `value_func` must be a pure function of its inputs. Debug builds
+    /// evaluate every hint twice while filling and fail with
+    /// `EvalError::NondeterministicHint` if the results differ.
+    pub fn hint<F>(&mut self, value_func: F, depends_on: Vec<NodeId>) -> NodeId
+    where
+        F: 'static + Fn(&[u32]) -> u32,
+    {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Hint(depends_on.clone()));
+        let node_id = self.create_node(None, true, depends_on);
+        *self.nodes[node_id].operation.borrow_mut() =
+            Some(self.masked(None, Rc::new(move |values| Ok(value_func(values)))));
+        node_id
+    }
+
+    /// Creates a hint like `hint`, but first checks that every node in
+    /// `depends_on` already exists, returning `BuilderError::InvalidNode`
+    /// for the first that doesn't.
+    ///
+    /// `hint` accepts ids of nodes that haven't been created yet, which only
+    /// fails once the graph is validated or filled.
+    pub fn try_hint<F>(&mut self, value_func: F, depends_on: Vec<NodeId>) -> Result<NodeId, BuilderError>
+    where
+        F: 'static + Fn(&[u32]) -> u32,
+    {
+        for &id in &depends_on {
+            self.node(id)?;
+        }
+        Ok(self.hint(value_func, depends_on))
+    }
+
+    /// Creates a hint whose function reads the builder itself, so it can use
+    /// the value of any node that is already filled, not only `depends_on`.
+    ///
+    /// The hint is still only computed once all of `depends_on` is filled,
+    /// and that is the only ordering guaranteed: a node read through the
+    /// builder is only reliably filled if it is one of `depends_on` or one of
+    /// their ancestors. Reads of other nodes may see `None`, or a value from
+    /// an earlier fill. Analyses of the graph structure, such as `paths` or
+    /// `output_depends_on`, don't see those reads either.
+    ///
+    /// The hint can only be computed while filling, through `fill_inputs`
+    /// or `value`. Evaluating it elsewhere, like in
+    /// `evaluate_into`, fails with `EvalError::DynamicHint`.
+    pub fn hint_dynamic<F>(&mut self, value_func: F, depends_on: Vec<NodeId>) -> NodeId
+    where
+        F: 'static + Fn(&Builder) -> u32,
+    {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Hint(depends_on.clone()));
+        let mut node = Node::new(None, true, depends_on);
+        *node.operation.borrow_mut() = Some(Rc::new(|_| Err(EvalError::DynamicHint)));
+        node.dynamic = Some(Rc::new(value_func));
+        self.push_node(node)
+    }
+
+    /// Creates a hint like `hint`, tagged with its purpose (such as `"sqrt"`)
+    /// so it can be found again with `hints_by_tag`.
+    pub fn hint_tagged<F>(&mut self, tag: &str, value_func: F, depends_on: Vec<NodeId>) -> NodeId
+    where
+        F: 'static + Fn(&[u32]) -> u32,
+    {
+        let node_id = self.hint(value_func, depends_on);
+        *self.nodes[node_id].tag.borrow_mut() = Some(tag.to_string());
+        node_id
+    }
+
+    /// Returns the hints created by `hint_tagged` with `tag`, in id order.
+    pub fn hints_by_tag(&self, tag: &str) -> Vec<NodeId> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].tag.borrow().as_deref() == Some(tag))
+            .collect()
+    }
+
+    /// Divides `a` by `b` with a hint, returning the quotient, and asserts
+    /// that `quotient * b == a`.
+    ///
+    /// The constraint only holds for exact division, so this also checks
+    /// that `b` divides `a`. Dividing by zero gives a quotient of 0, which
+    /// only satisfies the constraint if `a` is 0 too.
+    pub fn div_with_constraint(&mut self, a: &NodeId, b: &NodeId) -> NodeId {
+        let quotient = self.hint(|values| values[0].checked_div(values[1]).unwrap_or(0), vec![*a, *b]);
+        let product = self.mul(&quotient, b);
+        self.assert_equal(product, *a);
+        quotient
+    }
+
+    /// Creates a hint like `hint`, from a function computing a float.
+    ///
+    /// The result is truncated to a `u32`, like an `as` cast, unless strict
+    /// hints are enabled with `strict_hints`: then filling fails with
+    /// `EvalError::LossyHint` if the result is NaN, infinite, fractional or
+    /// out of range.
+    pub fn hint_f64<F>(&mut self, value_func: F, depends_on: Vec<NodeId>) -> NodeId
+    where
+        F: 'static + Fn(&[u32]) -> f64,
+    {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Hint(depends_on.clone()));
+        let strict = self.strict_hints;
+        let node_id = self.create_node(None, true, depends_on);
+        let operation: Operation = Rc::new(move |values| {
+            let value = value_func(values);
+            if strict && (value.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&value)) {
+                return Err(EvalError::LossyHint);
+            }
+            Ok(value as u32)
+        });
+        *self.nodes[node_id].operation.borrow_mut() = Some(self.masked(None, operation));
+        node_id
+    }
+
+    /// Enables or disables strict checking of `hint_f64` results. This
+    /// applies to hints created after the call.
+    pub fn strict_hints(&mut self, enabled: bool) {
+        self.strict_hints = enabled;
+    }
+
+    /// Copies `root` and every node it depends on into fresh nodes, returning
+    /// the copy of `root`.
+    ///
+    /// Inputs in the subgraph are copied as new, unfilled inputs, so the copy
+    /// can be filled independently of the original. Copies are created
+    /// parents first, with the subgraph's inputs and constants coming first
+    /// in their original order.
+    ///
+    /// Returns an error if `root` doesn't exist or depends on a cycle.
+    pub fn clone_subgraph(&mut self, root: NodeId) -> Result<NodeId, BuilderError> {
+        self.node(root)?;
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::CloneSubgraph(root));
+        let in_subgraph = self.dependency_closure(&[root]);
+        let mut copies: Vec<Option<NodeId>> = vec![None; self.nodes.len()];
+        for node_id in self.topological_order() {
+            if !in_subgraph[node_id] {
+                continue;
+            }
+            let original = Rc::clone(&self.nodes[node_id]);
+            let parents = original
+                .parents
+                .iter()
+                .map(|&parent| copies[parent].expect("Parents are copied first"))
+                .collect();
+            let node = original.with_parents(parents);
+            if !node.is_constant {
+                *node.value.borrow_mut() = None;
+                node.overflowed.set(false);
+            }
+            copies[node_id] = Some(self.push_node(node));
+        }
+        copies[root].ok_or(BuilderError::Cycle(root))
+    }
+
+    /// Returns the value of every node in id order, after `fill_inputs`.
+    ///
+    /// Prefixed with a 1, this is the assignment checked by `to_r1cs`.
+    pub fn witness(&self) -> Result<Vec<u32>, BuilderError> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(node_id, node)| node.value.borrow().ok_or(BuilderError::Unfilled(node_id)))
+            .collect()
+    }
+
+    /// Sets the value of every node from a witness in the format returned by
+    /// `witness`, without running any operations, so the constraints can be
+    /// checked against values computed elsewhere.
+    ///
+    /// Constants keep their own value, whatever the witness holds for them.
+    /// Returns an error, without changing anything, if `values` doesn't have
+    /// exactly one value per node.
+    pub fn load_witness(&mut self, values: &[u32]) -> Result<(), BuilderError> {
+        if values.len() != self.nodes.len() {
+            return Err(BuilderError::WitnessLength {
+                given: values.len(),
+                nodes: self.nodes.len(),
+            });
+        }
+        for (node, &value) in self.nodes.iter().zip(values) {
+            if !node.is_constant {
+                *node.value.borrow_mut() = Some(value);
+                node.overflowed.set(false);
+            }
+        }
+        self.solved_inputs.clear();
+        Ok(())
+    }
+
+    /// Returns the current value of a node, or `None` if it isn't filled or
+    /// doesn't exist.
+    pub fn get_value(&self, id: NodeId) -> Option<u32> {
+        self.node(id).ok().and_then(|node| *node.value.borrow())
+    }
+
+    /// Compares node values against `expected` `(node, value)` pairs,
+    /// returning every mismatch as `(node, expected, actual)`.
+    ///
+    /// `actual` is `None` for a node that isn't filled or doesn't exist, which
+    /// never matches.
+    pub fn assert_outputs(&self, expected: &[(NodeId, u32)]) -> Result<(), Vec<(NodeId, u32, Option<u32>)>> {
+        let mismatches: Vec<(NodeId, u32, Option<u32>)> = expected
+            .iter()
+            .map(|&(id, value)| (id, value, self.get_value(id)))
+            .filter(|&(_, value, actual)| actual != Some(value))
+            .collect();
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    /// Returns the nodes that don't hold a value, in id order.
+    ///
+    /// After a fill, these are the nodes that couldn't be computed, such as
+    /// everything downstream of an input that wasn't given.
+    pub fn unfilled_nodes(&self) -> Vec<NodeId> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].value.borrow().is_none())
+            .collect()
+    }
+
+    /// Returns the smallest and largest value held by any node, or `None`
+    /// if no node holds a value.
+    pub fn value_range(&self) -> Option<(u32, u32)> {
+        self.nodes
+            .iter()
+            .filter_map(|node| *node.value.borrow())
+            .fold(None, |range, value| match range {
+                Some((min, max)) => Some((value.min(min), value.max(max))),
+                None => Some((value, value)),
+            })
+    }
+
+    /// Computes a conservative `(min, max)` interval for every node, indexed
+    /// by node id, without evaluating the graph.
+    ///
+    /// `input_ranges` gives the inclusive range of input nodes as
+    /// `(handle, (min, max))` pairs; inputs that aren't given may take any
+    /// `u32`.
+    ///
+    /// Bounds are computed exactly, without wrapping, so a node whose upper
+    /// bound exceeds `u32::MAX` may overflow for some inputs in range. A
+    /// `sub`, `overflowing_add` or `overflowing_mul` that may wrap around, a
+    /// hint or a custom operation can take any `u32`.
+    ///
+    /// With a bit width below 32, an operation or hint whose bounds don't fit
+    /// in the bit width may wrap around, so it can take any value of that
+    /// width instead; see `with_bit_width`.
+    pub fn eval_ranges(&self, input_ranges: &[(NodeId, (u32, u32))]) -> Vec<(u64, u64)> {
+        let any = (0, u64::from(u32::MAX));
+        let mut ranges = vec![any; self.nodes.len()];
+        for &(node_id, (min, max)) in input_ranges {
+            if self.nodes.get(node_id).is_some_and(|node| node.is_input()) {
+                ranges[node_id] = (min.into(), max.into());
+            }
+        }
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            let parents: Vec<(u64, u64)> = node.parents.iter().map(|&id| ranges[id]).collect();
+            let sum = |ranges: &[(u64, u64)]| {
+                ranges.iter().fold((0u64, 0u64), |(min, max), &(lo, hi)| {
+                    (min.saturating_add(lo), max.saturating_add(hi))
+                })
+            };
+            let product = |ranges: &[(u64, u64)]| {
+                ranges.iter().fold((1u64, 1u64), |(min, max), &(lo, hi)| {
+                    (min.saturating_mul(lo), max.saturating_mul(hi))
+                })
+            };
+            let mask = u64::from(self.mask);
+            let wrapping = |(lo, hi): (u64, u64)| if hi > mask { (0, mask) } else { (lo, hi) };
+            ranges[node_id] = match node.kind {
+                _ if node.is_constant => {
+                    let value = u64::from(node.value.borrow().expect("Constants always have a value"));
+                    (value, value)
+                }
+                Some(OpKind::Add | OpKind::Sum) => sum(&parents),
+                Some(OpKind::Mul | OpKind::Product) => product(&parents),
+                Some(OpKind::OverflowingAdd) => wrapping(sum(&parents)),
+                Some(OpKind::OverflowingMul) => wrapping(product(&parents)),
+                Some(OpKind::Sub) => match parents[..] {
+                    [(a_lo, a_hi), (b_lo, b_hi)] if a_lo >= b_hi => (a_lo - b_hi, a_hi - b_lo),
+                    _ => any,
+                },
+                Some(OpKind::Min) => (parents[0].0.min(parents[1].0), parents[0].1.min(parents[1].1)),
+                Some(OpKind::Max) => (parents[0].0.max(parents[1].0), parents[0].1.max(parents[1].1)),
+                Some(OpKind::AbsDiff) => {
+                    let [(a_lo, a_hi), (b_lo, b_hi)] = parents[..] else {
+                        unreachable!("abs_diff has 2 parents")
+                    };
+                    let gap = a_lo.saturating_sub(b_hi).max(b_lo.saturating_sub(a_hi));
+                    (gap, a_hi.saturating_sub(b_lo).max(b_hi.saturating_sub(a_lo)))
+                }
+                Some(OpKind::Rem) => (0, parents[0].1.min(parents[1].1.saturating_sub(1))),
+                Some(OpKind::RomRead(rom)) => {
+                    let table = &self.roms[rom.0];
+                    match (table.iter().min(), table.iter().max()) {
+                        (Some(&min), Some(&max)) => (min.into(), max.into()),
+                        _ => any,
+                    }
+                }
+                Some(OpKind::ScalarAdd(c)) => sum(&[parents[0], (c.into(), c.into())]),
+                Some(OpKind::ScalarMul(c)) => product(&[parents[0], (c.into(), c.into())]),
+                Some(OpKind::Custom(_) | OpKind::Hint) | None => ranges[node_id],
+            };
+            if self.mask != u32::MAX && node.operation.borrow().is_some() && ranges[node_id].1 > mask {
+                ranges[node_id] = (0, mask);
+            }
+        }
+        ranges
+    }
+
+    /// Returns the nodes that currently hold `value`.
+    pub fn nodes_with_value(&self, value: u32) -> Vec<NodeId> {
+        (0..self.nodes.len())
+            .filter(|&id| *self.nodes[id].value.borrow() == Some(value))
+            .collect()
+    }
+
+    /// Compares the structure of this graph with `other`, matching nodes and
+    /// constraints by id.
+    pub fn diff(&self, other: &Builder) -> GraphDiff {
+        let shared = self.nodes.len().min(other.nodes.len());
+        GraphDiff {
+            added_nodes: (shared..other.nodes.len()).collect(),
+            removed_nodes: (shared..self.nodes.len()).collect(),
+            changed_nodes: (0..shared)
+                .filter(|&id| !self.nodes[id].same_operation(&other.nodes[id]))
+                .collect(),
+            added_constraints: other
+                .constraints
+                .iter()
+                .filter(|constraint| !self.constraints.contains(constraint))
+                .copied()
+                .collect(),
+            removed_constraints: self
+                .constraints
+                .iter()
+                .filter(|constraint| !other.constraints.contains(constraint))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if both nodes compute the same expression: the same
+    /// operation over structurally equal parents, or constants with the
+    /// same value. Node values play no part.
+    ///
+    /// Inputs and hints are only equal to themselves, since what they hold
+    /// can't be compared by structure.
+    pub fn are_structurally_equal(&self, a: NodeId, b: NodeId) -> bool {
+        self.structurally_equal(a, b, &mut HashMap::new())
+    }
+
+    fn structurally_equal(&self, a: NodeId, b: NodeId, known: &mut HashMap<(NodeId, NodeId), bool>) -> bool {
+        let (Ok(node_a), Ok(node_b)) = (self.node(a), self.node(b)) else {
+            return false;
+        };
+        if a == b {
+            return true;
+        }
+        if let Some(&equal) = known.get(&(a, b)) {
+            return equal;
+        }
+        // Pairs on a cycle are assumed unequal while they're being compared.
+        known.insert((a, b), false);
+        let equal = if node_a.is_constant || node_b.is_constant {
+            node_a.is_constant && node_b.is_constant && *node_a.value.borrow() == *node_b.value.borrow()
+        } else {
+            !node_a.is_hint
+                && !node_b.is_hint
+                && node_a.kind.is_some()
+                && node_a.kind == node_b.kind
+                && node_a.parents.len() == node_b.parents.len()
+                && node_a
+                    .parents
+                    .iter()
+                    .zip(&node_b.parents)
+                    .all(|(&parent_a, &parent_b)| self.structurally_equal(parent_a, parent_b, known))
+        };
+        known.insert((a, b), equal);
+        equal
+    }
+
+    /// Compiles the graph into a rank-1 constraint system.
+    ///
+    /// Every constant, `add`, `sub`, `mul`, scalar operation and
+    /// `assert_equal` becomes one constraint. Hints, non-arithmetic
+    /// operations such as ROM reads or `min`/`max`/`abs_diff`, and custom
+    /// operations add no constraints of their own: like hints in the graph,
+    /// they are only checked by the constraints they feed into. Inputs are
+    /// listed as public inputs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the builder has a bit width below 32, since the constraint
+    /// system only works modulo 2^32; see `with_bit_width`. Also panics on a
+    /// product of more than two factors from `flatten_associative`, which
+    /// would need intermediate variables that aren't part of the witness.
+    pub fn to_r1cs(&self) -> R1cs {
+        assert!(
+            self.mask == u32::MAX,
+            "Cannot compile a {}-bit graph to R1CS, which works modulo 2^32",
+            self.bit_width()
+        );
+        let var = |id: NodeId| id + 1;
+        let one = || vec![(R1cs::ONE, 1)];
+        let mut r1cs = R1cs::new(self.nodes.len());
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            let parents: Vec<usize> = node.parents.iter().map(|&id| var(id)).collect();
+            match node.kind {
+                Some(OpKind::Add | OpKind::OverflowingAdd | OpKind::Sum) => r1cs.push(
+                    parents.iter().map(|&p| (p, 1)).collect(),
+                    one(),
+                    vec![(var(node_id), 1)],
+                ),
+                Some(OpKind::Sub) => r1cs.push(
+                    vec![(parents[0], 1), (parents[1], u32::MAX)],
+                    one(),
+                    vec![(var(node_id), 1)],
+                ),
+                Some(OpKind::Mul | OpKind::OverflowingMul) => {
+                    r1cs.push(vec![(parents[0], 1)], vec![(parents[1], 1)], vec![(var(node_id), 1)])
+                }
+                Some(OpKind::Product) if parents.len() == 2 => {
+                    r1cs.push(vec![(parents[0], 1)], vec![(parents[1], 1)], vec![(var(node_id), 1)])
+                }
+                Some(OpKind::Product) => panic!(
+                    "Cannot compile node {}, a product of {} factors, to R1CS; compile before flatten_associative",
+                    node_id,
+                    parents.len()
+                ),
+                Some(OpKind::ScalarAdd(c)) => {
+                    r1cs.push(vec![(parents[0], 1), (R1cs::ONE, c)], one(), vec![(var(node_id), 1)])
+                }
+                Some(OpKind::ScalarMul(c)) => r1cs.push(vec![(parents[0], c)], one(), vec![(var(node_id), 1)]),
+                None if node.is_constant => {
+                    let value = node.value.borrow().expect("Constants always have a value");
+                    r1cs.push(vec![(R1cs::ONE, value)], one(), vec![(var(node_id), 1)]);
+                }
+                None if node.is_input() => r1cs.public_inputs.push(var(node_id)),
+                Some(
+                    OpKind::Min
+                    | OpKind::Max
+                    | OpKind::AbsDiff
+                    | OpKind::Rem
+                    | OpKind::RomRead(_)
+                    | OpKind::Custom(_)
+                    | OpKind::Hint,
+                )
+                | None => {}
+            }
+        }
+        for &(a, b) in &self.constraints {
+            r1cs.push(vec![(var(a), 1)], one(), vec![(var(b), 1)]);
+        }
+        r1cs
+    }
+
+    /// Attaches a free-form annotation to a node, such as a variable name or
+    /// source location, replacing any previous one.
+    pub fn set_metadata(&mut self, id: NodeId, meta: String) -> Result<(), BuilderError> {
+        *self.node(id)?.metadata.borrow_mut() = Some(meta);
+        Ok(())
+    }
+
+    /// Returns the annotation attached to a node with `set_metadata`.
+    pub fn metadata(&self, id: NodeId) -> Option<String> {
+        self.node(id).ok().and_then(|node| node.metadata.borrow().clone())
+    }
+
+    /// Returns the parents of a node, or `None` if it doesn't exist.
+    pub fn parents(&self, id: NodeId) -> Option<&[NodeId]> {
+        self.node(id).ok().map(|node| node.parents.as_slice())
+    }
+
+    /// Reconstructs the expression computing `output` as an infix string,
+    /// such as `((x * x) + x) + 5`.
+    ///
+    /// Inputs are named by their metadata, or `x<id>` if they have none.
+    /// Hints show only their dependencies, as `hint(a, b)`, and a node
+    /// reached again through a cycle or a missing id is shown as `#<id>`.
+    pub fn to_expression(&self, output: NodeId) -> String {
+        self.expression(output, false, &mut vec![false; self.nodes.len()])
+    }
+
+    fn expression(&self, node_id: NodeId, nested: bool, visiting: &mut Vec<bool>) -> String {
+        let Some(node) = self.nodes.get(node_id).filter(|_| !visiting[node_id]) else {
+            return format!("#{}", node_id);
+        };
+        if node.is_constant {
+            return node.value.borrow().expect("Constants always have a value").to_string();
+        }
+        if node.is_input() {
+            return node
+                .metadata
+                .borrow()
+                .clone()
+                .unwrap_or_else(|| format!("x{}", node_id));
+        }
+
+        // Arguments of function-style operations don't need parentheses.
+        let is_infix = matches!(
+            node.kind,
+            Some(
+                OpKind::Add
+                    | OpKind::OverflowingAdd
+                    | OpKind::Sum
+                    | OpKind::Sub
+                    | OpKind::Rem
+                    | OpKind::Mul
+                    | OpKind::OverflowingMul
+                    | OpKind::Product
+                    | OpKind::ScalarAdd(_)
+                    | OpKind::ScalarMul(_)
+            )
+        );
+        visiting[node_id] = true;
+        let mut operands: Vec<String> = node
+            .parents
+            .iter()
+            .map(|&id| self.expression(id, is_infix, visiting))
+            .collect();
+        visiting[node_id] = false;
+        let infix = |symbol: &str, operands: &[String]| {
+            let expression = operands.join(&format!(" {} ", symbol));
+            if nested {
+                format!("({})", expression)
+            } else {
+                expression
+            }
+        };
+        match node.kind {
+            Some(OpKind::Add | OpKind::OverflowingAdd | OpKind::Sum) => infix("+", &operands),
+            Some(OpKind::Sub) => infix("-", &operands),
+            Some(OpKind::Rem) => infix("%", &operands),
+            Some(OpKind::Mul | OpKind::OverflowingMul | OpKind::Product) => infix("*", &operands),
+            Some(OpKind::ScalarAdd(c)) => {
+                operands.push(c.to_string());
+                infix("+", &operands)
+            }
+            Some(OpKind::ScalarMul(c)) => {
+                operands.push(c.to_string());
+                infix("*", &operands)
+            }
+            Some(OpKind::Min) => format!("min({})", operands.join(", ")),
+            Some(OpKind::Max) => format!("max({})", operands.join(", ")),
+            Some(OpKind::AbsDiff) => format!("abs_diff({})", operands.join(", ")),
+            Some(OpKind::RomRead(rom)) => format!("rom{}[{}]", rom.0, operands.join(", ")),
+            Some(OpKind::Custom(index)) => format!("{}({})", self.custom_ops[index].0, operands.join(", ")),
+            Some(OpKind::Hint) | None => format!("hint({})", operands.join(", ")),
+        }
+    }
+
+    /// Substitutes `source` for the input node `input`: every node and
+    /// constraint that used `input` uses `source` instead. The input itself
+    /// is left in place, unused.
+    ///
+    /// Returns an error if either node doesn't exist or `input` isn't an
+    /// input node, and `BuilderError::Cycle` if `source` depends on `input`,
+    /// since the substitution would then make `source` its own ancestor.
+    pub fn bind_input(&mut self, input: NodeId, source: NodeId) -> Result<(), BuilderError> {
+        if !self.node(input)?.is_input() {
+            return Err(BuilderError::NotAnInput(input));
+        }
+        self.node(source)?;
+        if self.dependency_closure(&[source])[input] {
+            return Err(BuilderError::Cycle(source));
+        }
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::BindInput(input, source));
+        let rebind = |id: NodeId| if id == input { source } else { id };
+        for node_id in 0..self.nodes.len() {
+            let node = &self.nodes[node_id];
+            if node.parents.contains(&input) {
+                let parents = node.parents.iter().map(|&id| rebind(id)).collect();
+                self.nodes[node_id] = Rc::new(node.with_parents(parents));
+            }
+        }
+        for constraint in &mut self.constraints {
+            *constraint = (rebind(constraint.0), rebind(constraint.1));
+        }
+        Ok(())
+    }
+
+    /// Changes the value of the constant node `id` and clears the values
+    /// computed from it, so the next fill uses the new value.
+    ///
+    /// `reduce`, `simplify` and `specialize_constants` copy the value of a
+    /// constant into the nodes they build or decide on, and those nodes don't
+    /// read the constant afterwards. Changing such a constant would leave
+    /// them with the old value, so it fails with
+    /// `BuilderError::FoldedConstant` instead.
+    ///
+    /// Returns an error if the node doesn't exist or isn't a constant.
+    pub fn set_constant(&mut self, id: NodeId, new_value: u32) -> Result<(), BuilderError> {
+        if !self.node(id)?.is_constant {
+            return Err(BuilderError::NotAConstant(id));
+        }
+        if self.nodes[id].folded.get() {
+            return Err(BuilderError::FoldedConstant(id));
+        }
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::SetConstant(id, new_value));
+        *self.nodes[id].value.borrow_mut() = Some(new_value);
+        let mut stale = vec![false; self.nodes.len()];
+        stale[id] = true;
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            if node.parents.iter().any(|&parent| stale[parent]) {
+                stale[node_id] = true;
+                *node.value.borrow_mut() = None;
+                node.overflowed.set(false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the constant node `id` as copied into other nodes, so
+    /// `set_constant` refuses to change it.
+    fn fold_constant(&self, id: NodeId) {
+        self.nodes[id].folded.set(true);
+    }
+
+    /// Rewrites operations with a constant operand that don't change the
+    /// other operand: `x + 0` and `x - 0` become `x`, `x * 1` becomes `x`,
+    /// and `x * 0` becomes the zero constant.
+    ///
+    /// Consumers and constraints of a simplified node are rewired to its
+    /// replacement; the node itself is left in place, unused.
+    pub fn simplify(&mut self) {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::Simplify);
+        let mut replacement: Vec<NodeId> = (0..self.nodes.len()).collect();
+        for node_id in self.topological_order() {
+            let node = Rc::clone(&self.nodes[node_id]);
+            let parents: Vec<NodeId> = node.parents.iter().map(|&parent| replacement[parent]).collect();
+            if parents != node.parents {
+                self.nodes[node_id] = Rc::new(node.with_parents(parents.clone()));
+            }
+            let constant = |id: NodeId| {
+                let parent = &self.nodes[id];
+                if parent.is_constant {
+                    *parent.value.borrow()
+                } else {
+                    None
+                }
+            };
+            let (simplified, folded) = match (node.kind, parents.as_slice()) {
+                (Some(OpKind::Add | OpKind::OverflowingAdd), &[a, b]) if constant(b) == Some(0) => (a, b),
+                (Some(OpKind::Add | OpKind::OverflowingAdd), &[a, b]) if constant(a) == Some(0) => (b, a),
+                (Some(OpKind::Sub), &[a, b]) if constant(b) == Some(0) => (a, b),
+                (Some(OpKind::Mul | OpKind::OverflowingMul), &[a, b]) if constant(b) == Some(1) => (a, b),
+                (Some(OpKind::Mul | OpKind::OverflowingMul), &[a, b]) if constant(a) == Some(1) => (b, a),
+                (Some(OpKind::Mul | OpKind::OverflowingMul), &[a, _]) if constant(a) == Some(0) => (a, a),
+                (Some(OpKind::Mul | OpKind::OverflowingMul), &[_, b]) if constant(b) == Some(0) => (b, b),
+                _ => continue,
+            };
+            replacement[node_id] = simplified;
+            self.fold_constant(folded);
+        }
+        for constraint in &mut self.constraints {
+            constraint.0 = replacement.get(constraint.0).copied().unwrap_or(constraint.0);
+            constraint.1 = replacement.get(constraint.1).copied().unwrap_or(constraint.1);
+        }
+    }
+
+    /// Folds constant operands into the operations that use them: `x + c`
+    /// and `x * c` (in either order) become single-parent scalar operations
+    /// reading only from `x`.
+    ///
+    /// The specialized node keeps its id, so consumers and constraints are
+    /// unaffected; a constant left without consumers stays in place, unused.
+    pub fn specialize_constants(&mut self) {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::SpecializeConstants);
+        for node_id in 0..self.nodes.len() {
+            let node = Rc::clone(&self.nodes[node_id]);
+            let constant = |id: NodeId| {
+                let parent = self.nodes.get(id)?;
+                if parent.is_constant {
+                    *parent.value.borrow()
+                } else {
+                    None
+                }
+            };
+            let (kind, operand, folded): (OpKind, NodeId, NodeId) = match (node.kind, node.parents.as_slice()) {
+                (Some(OpKind::Add), &[a, b]) => match (constant(a), constant(b)) {
+                    (_, Some(c)) => (OpKind::ScalarAdd(c), a, b),
+                    (Some(c), None) => (OpKind::ScalarAdd(c), b, a),
+                    (None, None) => continue,
+                },
+                (Some(OpKind::Mul), &[a, b]) => match (constant(a), constant(b)) {
+                    (_, Some(c)) => (OpKind::ScalarMul(c), a, b),
+                    (Some(c), None) => (OpKind::ScalarMul(c), b, a),
+                    (None, None) => continue,
+                },
+                _ => continue,
+            };
+            self.fold_constant(folded);
+            let mut specialized = node.with_parents(vec![operand]);
+            specialized.kind = Some(kind);
+            *specialized.operation.borrow_mut() = Some(self.masked(Some(kind), kind.rewrite_operation()));
+            self.nodes[node_id] = Rc::new(specialized);
+        }
+    }
+
+    /// Merges chains of `add` or `mul` nodes into single n-ary nodes, so
+    /// `((a + b) + c) + d` becomes one sum over `a`, `b`, `c` and `d`.
+    ///
+    /// Only operations whose result is used by nothing but the next
+    /// operation in the chain are merged; shared intermediate results are
+    /// kept as operands. Merged nodes keep their ids and are left in place,
+    /// unused. `to_r1cs` can't compile products of more than two factors,
+    /// so compile to R1CS before flattening.
+    pub fn flatten_associative(&mut self) {
+        #[cfg(feature = "record")]
+        self.record(RecordedOp::FlattenAssociative);
+        let consumers = self.consumer_counts();
+        let mut used_elsewhere = vec![false; self.nodes.len()];
+        for id in self
+            .constrained_nodes()
+            .into_iter()
+            .chain(self.output_groups.values().flatten().copied())
+        {
+            if let Some(used) = used_elsewhere.get_mut(id) {
+                *used = true;
+            }
+        }
+        for node_id in self.topological_order() {
+            let node = Rc::clone(&self.nodes[node_id]);
+            let kind = match node.kind {
+                Some(OpKind::Add | OpKind::Sum) => OpKind::Sum,
+                Some(OpKind::Mul | OpKind::Product) => OpKind::Product,
+                _ => continue,
+            };
+            let mut operands = Vec::new();
+            let mut merged = false;
+            for &parent in &node.parents {
+                let parent_node = &self.nodes[parent];
+                let same_chain = match kind {
+                    OpKind::Sum => matches!(parent_node.kind, Some(OpKind::Add | OpKind::Sum)),
+                    _ => matches!(parent_node.kind, Some(OpKind::Mul | OpKind::Product)),
+                };
+                if same_chain && consumers[parent] == 1 && !used_elsewhere[parent] {
+                    operands.extend(&parent_node.parents);
+                    merged = true;
+                } else {
+                    operands.push(parent);
+                }
+            }
+            if !merged {
+                continue;
+            }
+            let mut flattened = node.with_parents(operands);
+            flattened.kind = Some(kind);
+            *flattened.operation.borrow_mut() = Some(self.masked(Some(kind), kind.rewrite_operation()));
+            self.nodes[node_id] = Rc::new(flattened);
+        }
+    }
+
+    /// Returns `true` if the node was created through `hint`.
+    pub fn is_hint(&self, id: NodeId) -> bool {
+        self.node(id).is_ok_and(|node| node.is_hint)
+    }
+
+    /// Returns the hint nodes whose values don't feed into any constraint.
+    ///
+    /// Hint values are computed outside of the graph's arithmetic, so a hint
+    /// that isn't (transitively) checked by an `assert_equal` can take any
+    /// value without the constraints noticing.
+    pub fn unconstrained_hints(&self) -> Vec<NodeId> {
+        let constrained = self.dependency_closure(&self.constrained_nodes());
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].is_hint && !constrained[id])
+            .collect()
+    }
+
+    /// Returns the input nodes that no other node or constraint uses, which
+    /// usually means an input was declared but forgotten.
+    pub fn unused_inputs(&self) -> Vec<NodeId> {
+        let consumers = self.consumer_counts();
+        let constrained = self.constrained_nodes();
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].is_input() && consumers[id] == 0 && !constrained.contains(&id))
+            .collect()
+    }
+
+    /// Returns `true` if exactly one node isn't used as a parent by any
+    /// other node, so the whole graph funnels into a single output.
+    /// Constraints don't count as uses.
+    pub fn is_single_output(&self) -> bool {
+        self.consumer_counts().iter().filter(|&&count| count == 0).count() == 1
+    }
+
+    /// Returns the constant nodes that no other node or constraint uses.
+    pub fn unused_constants(&self) -> Vec<NodeId> {
+        let consumers = self.consumer_counts();
+        let constrained = self.constrained_nodes();
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].is_constant && consumers[id] == 0 && !constrained.contains(&id))
+            .collect()
+    }
+
+    /// Lists how every node was created, in id order, for comparing how two
+    /// graphs were built.
+    ///
+    /// Hint and custom operation functions aren't part of the log, so two
+    /// graphs with the same log can still compute different values.
+    pub fn op_log(&self) -> Vec<OpLogEntry> {
+        self.nodes
+            .iter()
+            .map(|node| match node.kind {
+                _ if node.is_constant => {
+                    OpLogEntry::Constant(node.value.borrow().expect("Constants always have a value"))
+                }
+                _ if node.is_hint => OpLogEntry::Hint(node.parents.clone()),
+                Some(kind) => OpLogEntry::Operation(kind, node.parents.clone()),
+                None => OpLogEntry::Input,
+            })
+            .collect()
+    }
+
+    /// Returns the input nodes that `output` depends on, directly or through
+    /// other nodes, in id order. An input node depends on itself.
+    pub fn output_depends_on(&self, output: NodeId) -> Vec<NodeId> {
+        let reached = self.dependency_closure(&[output]);
+        (0..self.nodes.len())
+            .filter(|&id| reached[id] && self.nodes[id].is_input())
+            .collect()
+    }
+
+    /// Sums the weight of every operation node's kind, as a tunable measure
+    /// of how expensive the graph is to evaluate.
+    ///
+    /// Kinds missing from `weights` cost nothing, and kinds carrying data,
+    /// such as `ScalarMul(3)`, are looked up with that data. Each hint costs
+    /// the weight of `OpKind::Hint`. Inputs and constants cost nothing.
+    pub fn estimated_cost(&self, weights: &HashMap<OpKind, u64>) -> u64 {
+        self.nodes
+            .iter()
+            .map(|node| if node.is_hint { Some(OpKind::Hint) } else { node.kind })
+            .map(|kind| kind.and_then(|kind| weights.get(&kind).copied()).unwrap_or(0))
+            .sum()
+    }
+
+    /// Groups the nodes into connected components, where a node is connected
+    /// to its parents and to the other side of its constraints.
+    ///
+    /// Each component lists its nodes in id order, and the components are
+    /// ordered by their lowest node id.
+    pub fn components(&self) -> Vec<Vec<NodeId>> {
+        fn find(root: &mut [NodeId], mut id: NodeId) -> NodeId {
+            while root[id] != id {
+                root[id] = root[root[id]];
+                id = root[id];
+            }
+            id
+        }
+        let mut root: Vec<NodeId> = (0..self.nodes.len()).collect();
+        let edges = self
+            .nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(node_id, node)| node.parents.iter().map(move |&parent| (node_id, parent)))
+            .chain(self.constraints.iter().copied());
+        for (a, b) in edges {
+            if a < root.len() && b < root.len() {
+                let (a, b) = (find(&mut root, a), find(&mut root, b));
+                root[a.max(b)] = a.min(b);
+            }
+        }
+
+        let mut components: Vec<Vec<NodeId>> = Vec::new();
+        let mut index_of_root: HashMap<NodeId, usize> = HashMap::new();
+        for node_id in 0..self.nodes.len() {
+            let node_root = find(&mut root, node_id);
+            let index = *index_of_root.entry(node_root).or_insert_with(|| {
+                components.push(Vec::new());
+                components.len() - 1
+            });
+            components[index].push(node_id);
+        }
+        components
+    }
+
+    /// Returns `(in_degree, out_degree)` for every node, in id order.
+    ///
+    /// The in-degree is the number of parents and the out-degree the number
+    /// of times the node is used as a parent, so `mul(x, x)` counts twice
+    /// for `x`.
+    pub fn degrees(&self) -> Vec<(usize, usize)> {
+        self.nodes
+            .iter()
+            .zip(self.consumer_counts())
+            .map(|(node, consumers)| (node.parents.len(), consumers))
+            .collect()
+    }
+
+    /// Returns how many times each node is used as a parent.
+    fn consumer_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.nodes.len()];
+        for node in &self.nodes {
+            for &parent in &node.parents {
+                if let Some(count) = counts.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Returns how many nodes feed into at least one constraint or output
+    /// group. The rest of the graph can't affect any result.
+    pub fn live_node_count(&self) -> usize {
+        let mut roots = self.constrained_nodes();
+        roots.extend(self.output_groups.values().flatten());
+        self.dependency_closure(&roots).into_iter().filter(|&live| live).count()
+    }
+
+    /// Returns both sides of every constraint.
+    fn constrained_nodes(&self) -> Vec<NodeId> {
+        self.constraints.iter().flat_map(|&(a, b)| [a, b]).collect()
+    }
+
+    /// Marks every node that `roots` depend on, including the roots themselves.
+    fn dependency_closure(&self, roots: &[NodeId]) -> Vec<bool> {
+        let mut reached = vec![false; self.nodes.len()];
+        let mut stack = roots.to_vec();
+        while let Some(node_id) = stack.pop() {
+            if node_id >= reached.len() || reached[node_id] {
+                continue;
+            }
+            reached[node_id] = true;
+            stack.extend(&self.nodes[node_id].parents);
+        }
+        reached
+    }
+
+    /// Returns every path from `from` to `to` through the dependency edges,
+    /// each listed starting at `from` and ending at `to`.
+    ///
+    /// The number of paths can grow exponentially with the number of
+    /// reconverging branches, so this is meant for small graphs or nearby
+    /// nodes. Nodes on a cycle are never part of a path.
+    pub fn paths(&self, from: NodeId, to: NodeId) -> Vec<Vec<NodeId>> {
+        if from >= self.nodes.len() || to >= self.nodes.len() {
+            return Vec::new();
+        }
+        let mut reaches = vec![false; self.nodes.len()];
+        for node_id in self.topological_order() {
+            reaches[node_id] = node_id == from || self.nodes[node_id].parents.iter().any(|&id| reaches[id]);
+        }
+
+        let mut paths = Vec::new();
+        let mut path = vec![to];
+        self.collect_paths(from, &reaches, &mut path, &mut paths);
+        paths
+    }
+
+    /// Extends `path`, which is built backwards from the target, through
+    /// every parent that leads to `from`.
+    fn collect_paths(&self, from: NodeId, reaches: &[bool], path: &mut Vec<NodeId>, paths: &mut Vec<Vec<NodeId>>) {
+        let node_id = *path.last().expect("Paths are never empty");
+        if !reaches[node_id] {
+            return;
+        }
+        if node_id == from {
+            paths.push(path.iter().rev().copied().collect());
+            return;
+        }
+        let mut parents = self.nodes[node_id].parents.clone();
+        parents.sort_unstable();
+        parents.dedup();
+        for parent in parents {
+            path.push(parent);
+            self.collect_paths(from, reaches, path, paths);
+            path.pop();
+        }
+    }
+
+    /// Returns how many distinct nodes `id` depends on, directly or
+    /// indirectly, not counting itself. Returns 0 if it doesn't exist.
+    pub fn fan_in_size(&self, id: NodeId) -> usize {
+        if id >= self.nodes.len() {
+            return 0;
+        }
+        let reached = self.dependency_closure(&self.nodes[id].parents);
+        reached
+            .iter()
+            .enumerate()
+            .filter(|&(node_id, &reached)| reached && node_id != id)
+            .count()
+    }
+
+    /// Returns the `(source, sink)` pairs where the value of `source` fans
+    /// out to different consumers and reconverges at `sink`, sorted.
+    ///
+    /// A pair is listed when at least two different parents of `sink` depend
+    /// on (or are) `source`, and those paths split right at `source`, so
+    /// nodes further upstream of the fan-out aren't listed as well.
+    pub fn diamonds(&self) -> Vec<(NodeId, NodeId)> {
+        let mut consumers: Vec<Vec<NodeId>> = vec![Vec::new(); self.nodes.len()];
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            for &parent in &node.parents {
+                if parent < consumers.len() && !consumers[parent].contains(&node_id) {
+                    consumers[parent].push(node_id);
+                }
+            }
+        }
+
+        let mut diamonds = Vec::new();
+        for (sink, node) in self.nodes.iter().enumerate() {
+            let mut parents = node.parents.clone();
+            parents.sort_unstable();
+            parents.dedup();
+            if parents.len() < 2 {
+                continue;
+            }
+            let upstream = self.dependency_closure(&[sink]);
+            let reached: Vec<Vec<bool>> = parents
+                .iter()
+                .map(|&parent| self.dependency_closure(&[parent]))
+                .collect();
+            for source in (0..self.nodes.len()).filter(|&id| id != sink && upstream[id]) {
+                let converging = reached.iter().filter(|reached| reached[source]).count();
+                let branches = consumers[source].iter().filter(|&&consumer| upstream[consumer]).count();
+                if converging >= 2 && branches >= 2 {
+                    diamonds.push((source, sink));
+                }
+            }
+        }
+        diamonds.sort_unstable();
+        diamonds
+    }
+
+    /// Groups the nodes into dependency levels: inputs and constants are on
+    /// level 0, and every other node is one level above its deepest parent.
+    ///
+    /// All nodes in a level only depend on earlier levels, so they can be
+    /// evaluated in any order. Nodes that lie on (or depend on) a cycle are
+    /// left out.
+    pub fn levels(&self) -> Vec<Vec<NodeId>> {
+        let mut depth = vec![0; self.nodes.len()];
+        let mut levels: Vec<Vec<NodeId>> = Vec::new();
+        for node_id in self.topological_order() {
+            depth[node_id] = self.nodes[node_id]
+                .parents
+                .iter()
+                .map(|&parent| depth[parent] + 1)
+                .max()
+                .unwrap_or(0);
+            if levels.len() <= depth[node_id] {
+                levels.resize(depth[node_id] + 1, Vec::new());
+            }
+            levels[depth[node_id]].push(node_id);
+        }
+        for level in &mut levels {
+            level.sort_unstable();
+        }
+        levels
+    }
+
+    /// Returns the largest number of multiplications along any path through
+    /// the graph, the cost that matters for FHE and many ZK backends.
+    ///
+    /// `mul` and `overflowing_mul` nodes count as one, and a `Product` of `n`
+    /// parents as the `ceil(log2(n))` levels of a balanced tree. Other
+    /// operations, including multiplying by a constant with `ScalarMul`, and
+    /// nodes on a cycle don't count.
+    pub fn mul_depth(&self) -> usize {
+        let mut depth = vec![0; self.nodes.len()];
+        for node_id in self.topological_order() {
+            let node = &self.nodes[node_id];
+            let own = match node.kind {
+                Some(OpKind::Mul | OpKind::OverflowingMul) => 1,
+                Some(OpKind::Product) => node.parents.len().next_power_of_two().trailing_zeros() as usize,
+                _ => 0,
+            };
+            depth[node_id] = own + node.parents.iter().map(|&parent| depth[parent]).max().unwrap_or(0);
+        }
+        depth.into_iter().max().unwrap_or(0)
+    }
+
+    /// Returns the nodes in an order where every node comes after its
+    /// parents. Nodes that can't be ordered because of a cycle or a missing
+    /// parent are left out.
+    fn topological_order(&self) -> Vec<NodeId> {
+        let mut pending: Vec<usize> = self.nodes.iter().map(|node| node.parents.len()).collect();
+        let mut children: Vec<Vec<NodeId>> = vec![Vec::new(); self.nodes.len()];
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            for &parent in &node.parents {
+                if let Some(consumers) = children.get_mut(parent) {
+                    consumers.push(node_id);
+                }
+            }
+        }
+
+        let mut order: Vec<NodeId> = (0..self.nodes.len()).filter(|&id| pending[id] == 0).collect();
+        let mut next = 0;
+        while next < order.len() {
+            for &child in &children[order[next]] {
+                pending[child] -= 1;
+                if pending[child] == 0 {
+                    order.push(child);
+                }
+            }
+            next += 1;
+        }
+        order
+    }
+
+    /// Checks that the graph can be evaluated: every parent and constrained
+    /// node exists, every operation (other than a hint) has parents, and
+    /// there are no cycles.
+    pub fn validate(&self) -> Result<(), BuilderError> {
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            for &parent in &node.parents {
+                self.node(parent)?;
+            }
+            if node.parents.is_empty() && !node.is_hint && node.operation.borrow().is_some() {
+                return Err(BuilderError::NoParents(node_id));
+            }
+        }
+        for &(a, b) in &self.constraints {
+            self.node(a)?;
+            self.node(b)?;
+        }
+        match self.find_cycle() {
+            Some(node_id) => Err(BuilderError::Cycle(node_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the graph contains no dependency cycles.
+    ///
+    /// Only hints can introduce cycles, since their dependencies may refer
+    /// to nodes created after them. Dependencies on nodes that don't exist
+    /// are ignored here.
+    pub fn is_acyclic(&self) -> bool {
+        self.find_cycle().is_none()
+    }
+
+    /// Returns a node that lies on a dependency cycle, if there is one.
+    fn find_cycle(&self) -> Option<NodeId> {
+        // 0 = unvisited, 1 = on the current DFS path, 2 = done
+        let mut state = vec![0u8; self.nodes.len()];
+        for start in 0..self.nodes.len() {
+            if state[start] != 0 {
+                continue;
+            }
+            let mut stack = vec![(start, 0)];
+            state[start] = 1;
+            while let Some(&mut (node_id, ref mut next)) = stack.last_mut() {
+                let parents = &self.nodes[node_id].parents;
+                if let Some(&parent) = parents.get(*next) {
+                    *next += 1;
+                    match state.get(parent) {
+                        Some(0) => {
+                            state[parent] = 1;
+                            stack.push((parent, 0));
+                        }
+                        Some(1) => return Some(parent),
+                        _ => {}
+                    }
+                } else {
+                    state[node_id] = 2;
+                    stack.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_operation_without_parents() {
+        // The public API can't create such a node, so build it by hand.
+        let mut builder = Builder::new();
+        let node_id = builder.add_operation(OpKind::Add, Vec::new(), Rc::new(|values| Ok(values[0] + values[1])));
+
+        assert_eq!(builder.validate(), Err(BuilderError::NoParents(node_id)));
+        assert_eq!(builder.fill_inputs(&[]), Err(BuilderError::NoParents(node_id)));
+    }
+
+    #[test]
+    fn stream_drops_consumed_values() {
+        // A chain of 100 additions only ever needs a couple of values live.
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let one = builder.constant(1);
+        let mut last = x;
+        for _ in 0..100 {
+            last = builder.add(&last, &one);
+        }
+
+        let mut peak = 0;
+        assert_eq!(builder.stream(&[(x, 5)], &[last], &mut peak), vec![105]);
+        assert!(peak <= 3, "peak was {}", peak);
     }
 }
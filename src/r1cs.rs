@@ -0,0 +1,62 @@
+/// A sparse linear combination of variables, as `(variable, coefficient)` pairs.
+pub type LinearCombination = Vec<(usize, u32)>;
+
+/// A rank-1 constraint system: for every row `i`,
+/// `<a[i], w> * <b[i], w> == <c[i], w>` over the assignment `w`.
+///
+/// Variable 0 is the constant 1, and variable `id + 1` holds the value of
+/// node `id`. Arithmetic wraps modulo 2^32, matching the `u32` node values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct R1cs {
+    pub num_variables: usize,
+    /// Variables holding the graph's inputs.
+    pub public_inputs: Vec<usize>,
+    pub a: Vec<LinearCombination>,
+    pub b: Vec<LinearCombination>,
+    pub c: Vec<LinearCombination>,
+}
+
+impl R1cs {
+    /// Index of the variable that always holds 1.
+    pub const ONE: usize = 0;
+
+    pub(crate) fn new(num_nodes: usize) -> Self {
+        Self {
+            num_variables: num_nodes + 1,
+            public_inputs: Vec::new(),
+            a: Vec::new(),
+            b: Vec::new(),
+            c: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, a: LinearCombination, b: LinearCombination, c: LinearCombination) {
+        self.a.push(a);
+        self.b.push(b);
+        self.c.push(c);
+    }
+
+    /// Returns the number of constraints.
+    pub fn len(&self) -> usize {
+        self.a.len()
+    }
+
+    /// Returns `true` if there are no constraints.
+    pub fn is_empty(&self) -> bool {
+        self.a.is_empty()
+    }
+
+    /// Checks every constraint against `assignment`, which must start with
+    /// the constant 1 followed by one value per node.
+    pub fn is_satisfied(&self, assignment: &[u32]) -> bool {
+        if assignment.len() != self.num_variables || assignment[Self::ONE] != 1 {
+            return false;
+        }
+        let eval = |lc: &LinearCombination| {
+            lc.iter().fold(0u32, |acc, &(var, coeff)| {
+                acc.wrapping_add(coeff.wrapping_mul(assignment[var]))
+            })
+        };
+        (0..self.len()).all(|i| eval(&self.a[i]).wrapping_mul(eval(&self.b[i])) == eval(&self.c[i]))
+    }
+}
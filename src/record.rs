@@ -0,0 +1,283 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::{Builder, BuilderError, Constraint, NodeId, RomHandle};
+
+/// A single recorded call on a `Builder`, see `Builder::recording`.
+///
+/// Each operation is written as one line of text, e.g. `add 0 1`, and can be
+/// parsed back with `str::parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedOp {
+    /// The bit width of a builder created with
+    /// `Builder::recording_with_bit_width`. Only valid as the first
+    /// operation.
+    BitWidth(u32),
+    Init,
+    Constant(u32),
+    Add(NodeId, NodeId),
+    Sub(NodeId, NodeId),
+    Mul(NodeId, NodeId),
+    OverflowingAdd(NodeId, NodeId),
+    OverflowingMul(NodeId, NodeId),
+    Min(NodeId, NodeId),
+    Max(NodeId, NodeId),
+    AbsDiff(NodeId, NodeId),
+    Rem(NodeId, NodeId),
+    /// A registered operation applied to `parents`. Its function isn't
+    /// recorded.
+    Apply {
+        name: String,
+        parents: Vec<NodeId>,
+    },
+    /// A hint over the given dependencies. Its function isn't recorded.
+    Hint(Vec<NodeId>),
+    Rom(Vec<u32>),
+    RomRead {
+        rom: usize,
+        index: NodeId,
+    },
+    AssertEqual(NodeId, NodeId),
+    ImportConstraints(Vec<Constraint>),
+    CloneSubgraph(NodeId),
+    BindInput(NodeId, NodeId),
+    SetConstant(NodeId, u32),
+    Simplify,
+    SpecializeConstants,
+    FlattenAssociative,
+}
+
+impl Display for RecordedOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = |values: &[usize]| values.iter().map(|value| format!(" {}", value)).collect::<String>();
+        match self {
+            RecordedOp::BitWidth(bits) => write!(f, "bit_width {}", bits),
+            RecordedOp::Init => write!(f, "init"),
+            RecordedOp::Constant(value) => write!(f, "constant {}", value),
+            RecordedOp::Add(a, b) => write!(f, "add {} {}", a, b),
+            RecordedOp::Sub(a, b) => write!(f, "sub {} {}", a, b),
+            RecordedOp::Mul(a, b) => write!(f, "mul {} {}", a, b),
+            RecordedOp::OverflowingAdd(a, b) => write!(f, "overflowing_add {} {}", a, b),
+            RecordedOp::OverflowingMul(a, b) => write!(f, "overflowing_mul {} {}", a, b),
+            RecordedOp::Min(a, b) => write!(f, "min {} {}", a, b),
+            RecordedOp::Max(a, b) => write!(f, "max {} {}", a, b),
+            RecordedOp::AbsDiff(a, b) => write!(f, "abs_diff {} {}", a, b),
+            RecordedOp::Rem(a, m) => write!(f, "rem {} {}", a, m),
+            RecordedOp::Apply { name, parents } => write!(f, "apply {}{}", name, list(parents)),
+            RecordedOp::Hint(depends_on) => write!(f, "hint{}", list(depends_on)),
+            RecordedOp::Rom(values) => {
+                let values: Vec<usize> = values.iter().map(|&value| value as usize).collect();
+                write!(f, "rom{}", list(&values))
+            }
+            RecordedOp::RomRead { rom, index } => write!(f, "rom_read {} {}", rom, index),
+            RecordedOp::AssertEqual(a, b) => write!(f, "assert_equal {} {}", a, b),
+            RecordedOp::ImportConstraints(constraints) => {
+                let ids: Vec<usize> = constraints.iter().flat_map(|&(a, b)| [a, b]).collect();
+                write!(f, "import_constraints{}", list(&ids))
+            }
+            RecordedOp::CloneSubgraph(root) => write!(f, "clone_subgraph {}", root),
+            RecordedOp::BindInput(input, source) => write!(f, "bind_input {} {}", input, source),
+            RecordedOp::SetConstant(id, value) => write!(f, "set_constant {} {}", id, value),
+            RecordedOp::Simplify => write!(f, "simplify"),
+            RecordedOp::SpecializeConstants => write!(f, "specialize_constants"),
+            RecordedOp::FlattenAssociative => write!(f, "flatten_associative"),
+        }
+    }
+}
+
+/// A line that isn't a valid `RecordedOp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOpError(String);
+
+impl Display for ParseOpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid recorded operation: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseOpError {}
+
+impl FromStr for RecordedOp {
+    type Err = ParseOpError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let err = || ParseOpError(line.to_string());
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or_else(err)?;
+        // `apply` is the only operation with a non-numeric argument.
+        let op_name = if name == "apply" { words.next() } else { None };
+        let args: Vec<usize> = words
+            .map(|word| word.parse().map_err(|_| err()))
+            .collect::<Result<_, _>>()?;
+        let op = match (name, args.as_slice()) {
+            ("bit_width", &[bits]) if (1..=32).contains(&bits) => RecordedOp::BitWidth(bits as u32),
+            ("init", []) => RecordedOp::Init,
+            ("constant", &[value]) => RecordedOp::Constant(u32::try_from(value).map_err(|_| err())?),
+            ("add", &[a, b]) => RecordedOp::Add(a, b),
+            ("sub", &[a, b]) => RecordedOp::Sub(a, b),
+            ("mul", &[a, b]) => RecordedOp::Mul(a, b),
+            ("overflowing_add", &[a, b]) => RecordedOp::OverflowingAdd(a, b),
+            ("overflowing_mul", &[a, b]) => RecordedOp::OverflowingMul(a, b),
+            ("min", &[a, b]) => RecordedOp::Min(a, b),
+            ("max", &[a, b]) => RecordedOp::Max(a, b),
+            ("abs_diff", &[a, b]) => RecordedOp::AbsDiff(a, b),
+            ("rem", &[a, m]) => RecordedOp::Rem(a, m),
+            ("apply", parents) => RecordedOp::Apply {
+                name: op_name.ok_or_else(err)?.to_string(),
+                parents: parents.to_vec(),
+            },
+            ("hint", depends_on) => RecordedOp::Hint(depends_on.to_vec()),
+            ("rom", values) => RecordedOp::Rom(
+                values
+                    .iter()
+                    .map(|&value| u32::try_from(value).map_err(|_| err()))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ("rom_read", &[rom, index]) => RecordedOp::RomRead { rom, index },
+            ("assert_equal", &[a, b]) => RecordedOp::AssertEqual(a, b),
+            ("import_constraints", ids) if ids.len() % 2 == 0 => {
+                RecordedOp::ImportConstraints(ids.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+            }
+            ("clone_subgraph", &[root]) => RecordedOp::CloneSubgraph(root),
+            ("bind_input", &[input, source]) => RecordedOp::BindInput(input, source),
+            ("set_constant", &[id, value]) => RecordedOp::SetConstant(id, u32::try_from(value).map_err(|_| err())?),
+            ("simplify", []) => RecordedOp::Simplify,
+            ("specialize_constants", []) => RecordedOp::SpecializeConstants,
+            ("flatten_associative", []) => RecordedOp::FlattenAssociative,
+            _ => return Err(err()),
+        };
+        Ok(op)
+    }
+}
+
+impl Builder {
+    /// Creates a new builder that records every call that adds nodes or
+    /// constraints, see `recorded_ops`.
+    pub fn recording() -> Self {
+        let mut builder = Builder::new();
+        builder.log = Some(Vec::new());
+        builder
+    }
+
+    /// Creates a new recording builder with the given bit width, see
+    /// `with_bit_width`. The width is recorded as the first operation, so
+    /// `replay` rebuilds a graph of the same width.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is 0 or more than 32.
+    pub fn recording_with_bit_width(bits: u32) -> Self {
+        let mut builder = Builder::with_bit_width(bits);
+        builder.log = Some(vec![RecordedOp::BitWidth(bits)]);
+        builder
+    }
+
+    /// Returns the calls recorded so far, or an empty slice if the builder
+    /// wasn't created with `recording`.
+    pub fn recorded_ops(&self) -> &[RecordedOp] {
+        self.log.as_deref().unwrap_or_default()
+    }
+
+    /// Rebuilds a graph by replaying recorded calls on a new recording
+    /// builder.
+    ///
+    /// Hint and custom operation functions aren't recorded, so replaying a
+    /// hint or `apply` fails with `BuilderError::UnreplayableHint` holding
+    /// its position in `ops`. A read from a table that hasn't been created
+    /// yet fails with `BuilderError::InvalidRom`, a reference to a node that
+    /// hasn't been created yet with `BuilderError::InvalidNode`, and a
+    /// `BitWidth` anywhere but first with `BuilderError::MisplacedBitWidth`.
+    pub fn replay(ops: &[RecordedOp]) -> Result<Builder, BuilderError> {
+        let mut builder = Builder::recording();
+        for (position, op) in ops.iter().enumerate() {
+            match *op {
+                RecordedOp::BitWidth(bits) if position == 0 => builder = Builder::recording_with_bit_width(bits),
+                RecordedOp::BitWidth(_) => return Err(BuilderError::MisplacedBitWidth(position)),
+                RecordedOp::Init => {
+                    builder.init();
+                }
+                RecordedOp::Constant(value) => {
+                    builder.constant(value);
+                }
+                RecordedOp::Add(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.add(&a, &b);
+                }
+                RecordedOp::Sub(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.sub(&a, &b);
+                }
+                RecordedOp::Mul(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.mul(&a, &b);
+                }
+                RecordedOp::OverflowingAdd(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.overflowing_add(&a, &b);
+                }
+                RecordedOp::OverflowingMul(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.overflowing_mul(&a, &b);
+                }
+                RecordedOp::Min(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.min(&a, &b);
+                }
+                RecordedOp::Max(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.max(&a, &b);
+                }
+                RecordedOp::AbsDiff(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.abs_diff(&a, &b);
+                }
+                RecordedOp::Rem(a, m) => {
+                    builder.check_nodes(&[a, m])?;
+                    builder.rem_node(&a, &m);
+                }
+                RecordedOp::Apply { .. } | RecordedOp::Hint(_) => return Err(BuilderError::UnreplayableHint(position)),
+                RecordedOp::Rom(ref values) => {
+                    builder.rom(values);
+                }
+                RecordedOp::RomRead { rom, index } => {
+                    builder.check_nodes(&[index])?;
+                    builder.rom_read(&RomHandle(rom), &index)?;
+                }
+                RecordedOp::AssertEqual(a, b) => {
+                    builder.check_nodes(&[a, b])?;
+                    builder.assert_equal(a, b);
+                }
+                RecordedOp::ImportConstraints(ref constraints) => {
+                    for &(a, b) in constraints {
+                        builder.check_nodes(&[a, b])?;
+                    }
+                    builder.import_constraints(constraints.clone());
+                }
+                RecordedOp::CloneSubgraph(root) => {
+                    builder.clone_subgraph(root)?;
+                }
+                RecordedOp::BindInput(input, source) => builder.bind_input(input, source)?,
+                RecordedOp::SetConstant(id, value) => builder.set_constant(id, value)?,
+                RecordedOp::Simplify => builder.simplify(),
+                RecordedOp::SpecializeConstants => builder.specialize_constants(),
+                RecordedOp::FlattenAssociative => builder.flatten_associative(),
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Returns `BuilderError::InvalidNode` for the first of `ids` that
+    /// doesn't exist.
+    fn check_nodes(&self, ids: &[NodeId]) -> Result<(), BuilderError> {
+        for &id in ids {
+            self.node(id)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, op: RecordedOp) {
+        if let Some(log) = &mut self.log {
+            log.push(op);
+        }
+    }
+}
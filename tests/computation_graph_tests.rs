@@ -1,8 +1,10 @@
-use cgl::Builder;
+
+use cgl::{Builder, BuilderError, Constraint, DecodeError, EvalError, GraphDiff, OpKind, OpLogEntry, RunResult};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     // Example 1: f(x) = x^2 + x + 5
     #[test]
@@ -14,8 +16,7 @@ mod tests {
         let five = builder.constant(5);
         let _y = builder.add(&x_squared_plus_x, &five);
 
-        let inputs = vec![Some(3)];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[(x, 3)]).unwrap();
         assert!(builder.check_constraints());
     }
 
@@ -32,8 +33,7 @@ mod tests {
         let c_times_8 = builder.mul(&c, &eight);
         builder.assert_equal(b, c_times_8);
 
-        let inputs = vec![Some(7)];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[(a, 7)]).unwrap();
         assert!(builder.check_constraints());
     }
 
@@ -49,8 +49,7 @@ mod tests {
         let computed_sq = builder.mul(&sqrt_x_plus_7, &sqrt_x_plus_7);
         builder.assert_equal(computed_sq, x_plus_7);
 
-        let inputs = vec![Some(9)];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[(x, 9)]).unwrap();
         assert!(builder.check_constraints());
     }
 
@@ -60,8 +59,7 @@ mod tests {
         let mut builder = Builder::new();
         let _five = builder.constant(5);
 
-        let inputs = vec![None; 1]; // No inputs needed for constants
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[]).unwrap(); // No inputs needed for constants
         assert!(builder.check_constraints());
     }
 
@@ -73,8 +71,7 @@ mod tests {
         let zero_b = builder.constant(0);
         let sum = builder.add(&zero_a, &zero_b);
 
-        let inputs = vec![None; 2];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[]).unwrap();
         builder.assert_equal(sum, zero_a);
         assert!(builder.check_constraints());
     }
@@ -86,8 +83,7 @@ mod tests {
         let x = builder.constant(10);
         let _sqrt_x = builder.hint(|values| (values[0] as f64).sqrt() as u32, vec![x]);
 
-        let inputs = vec![None; 1];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[]).unwrap();
         // There's no constraint to check for this non-perfect square hint
     }
 
@@ -101,8 +97,2357 @@ mod tests {
         let six_alt = builder.add(&three, &three);
         builder.assert_equal(six, six_alt);
 
-        let inputs = vec![None; 2];
-        builder.fill_nodes(inputs);
+        builder.fill_inputs(&[]).unwrap();
+        assert!(builder.check_constraints());
+    }
+
+    #[test]
+    fn is_acyclic_for_regular_graph() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let _y = builder.hint(|values| values[0] + 1, vec![x_squared]);
+
+        assert!(builder.is_acyclic());
+    }
+
+    #[test]
+    fn is_acyclic_detects_hint_cycle() {
+        let mut builder = Builder::new();
+        let _x = builder.init();
+        // Node 1 depends on node 2, which depends back on node 1.
+        let a = builder.hint(|values| values[0], vec![2]);
+        let _b = builder.hint(|values| values[0], vec![a]);
+
+        assert!(!builder.is_acyclic());
+    }
+
+    #[test]
+    fn is_hint_classifies_nodes() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let one = builder.constant(1);
+        let b = builder.add(&a, &one);
+
+        let c = builder.hint(|values| values[0] / 8, vec![b]);
+        let eight = builder.constant(8);
+        let c_times_8 = builder.mul(&c, &eight);
+        builder.assert_equal(b, c_times_8);
+
+        assert!(builder.is_hint(c));
+        for id in [a, one, b, eight, c_times_8] {
+            assert!(!builder.is_hint(id));
+        }
+    }
+
+    #[test]
+    fn unconstrained_hints_accepts_constrained_hint() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let seven = builder.constant(7);
+        let x_plus_7 = builder.add(&x, &seven);
+
+        let sqrt_x_plus_7 = builder.hint(|values| (values[0] as f64).sqrt() as u32, vec![x_plus_7]);
+        let computed_sq = builder.mul(&sqrt_x_plus_7, &sqrt_x_plus_7);
+        builder.assert_equal(computed_sq, x_plus_7);
+
+        assert!(builder.unconstrained_hints().is_empty());
+    }
+
+    #[test]
+    fn unconstrained_hints_flags_unchecked_hint() {
+        let mut builder = Builder::new();
+        let x = builder.constant(10);
+        let sqrt_x = builder.hint(|values| (values[0] as f64).sqrt() as u32, vec![x]);
+        let doubled = builder.hint(|values| values[0] * 2, vec![x]);
+        let four = builder.constant(4);
+        let twenty = builder.constant(20);
+        let checked = builder.add(&doubled, &four);
+        let twenty_four = builder.add(&twenty, &four);
+        builder.assert_equal(checked, twenty_four);
+
+        assert_eq!(builder.unconstrained_hints(), vec![sqrt_x]);
+    }
+
+    #[test]
+    fn fill_inputs_by_handle() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        // A constant inserted between the inputs doesn't shift `y`'s handle.
+        let five = builder.constant(5);
+        let y = builder.init();
+        let x_times_y = builder.mul(&x, &y);
+        let sum = builder.add(&x_times_y, &five);
+        let expected = builder.constant(17);
+        builder.assert_equal(sum, expected);
+
+        builder.fill_inputs(&[(x, 3), (y, 4)]).unwrap();
+        assert!(builder.check_constraints());
+    }
+
+    #[test]
+    fn fill_and_check_passes_and_fails() {
+        let build = || {
+            let mut builder = Builder::new();
+            let a = builder.init();
+            let one = builder.constant(1);
+            let b = builder.add(&a, &one);
+            let c = builder.hint(|values| values[0] / 8, vec![b]);
+            let eight = builder.constant(8);
+            let c_times_8 = builder.mul(&c, &eight);
+            builder.assert_equal(b, c_times_8);
+            (builder, a, b, c_times_8)
+        };
+
+        let (mut builder, a, _, _) = build();
+        assert_eq!(builder.fill_and_check(&[(a, 7)]), Ok(()));
+
+        // 9 / 8 truncates to 1, so 1 * 8 != 10.
+        let (mut builder, a, b, c_times_8) = build();
+        let Err(BuilderError::Constraint(err)) = builder.fill_and_check(&[(a, 9)]) else {
+            panic!("expected a constraint error");
+        };
+        assert_eq!(err.index, 0);
+        assert_eq!((err.left, err.right), (b, c_times_8));
+        assert_eq!((err.left_value, err.right_value), (Some(10), Some(8)));
+    }
+
+    #[test]
+    fn levels_group_independent_nodes() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let five = builder.constant(5);
+        let x_plus_y = builder.add(&x, &y);
+        let x_times_5 = builder.mul(&x, &five);
+        let total = builder.add(&x_plus_y, &x_times_5);
+
+        assert_eq!(
+            builder.levels(),
+            vec![vec![x, y, five], vec![x_plus_y, x_times_5], vec![total]]
+        );
+    }
+
+    #[test]
+    fn clone_subgraph_fills_independently() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared, &five);
+
+        let y_copy = builder.clone_subgraph(y).unwrap();
+        // The copied input is created first, right after the original nodes.
+        let x_copy = y + 1;
+        assert_ne!(y_copy, y);
+
+        let expected_y = builder.constant(14);
+        let expected_y_copy = builder.constant(21);
+        builder.assert_equal(y, expected_y);
+        builder.assert_equal(y_copy, expected_y_copy);
+
+        builder.fill_inputs(&[(x, 3), (x_copy, 4)]).unwrap();
+        assert!(builder.check_constraints());
+    }
+
+    #[test]
+    fn reduce_builds_balanced_tree() {
+        let level_of = |builder: &Builder, id| builder.levels().iter().position(|level| level.contains(&id));
+
+        let mut builder = Builder::new();
+        let values: Vec<_> = (1..=8).map(|_| builder.init()).collect();
+        let sum = builder.reduce(&values, OpKind::Add);
+        let expected = builder.constant(36);
+        builder.assert_equal(sum, expected);
+        let inputs: Vec<_> = values.iter().copied().zip(1..=8).collect();
+        builder.fill_inputs(&inputs).unwrap();
+        assert!(builder.check_constraints());
+
+        let mut chained = Builder::new();
+        let values: Vec<_> = (1..=8).map(|_| chained.init()).collect();
+        let chain_sum = values[1..]
+            .iter()
+            .fold(values[0], |acc, value| chained.add(&acc, value));
+
+        assert_eq!(level_of(&builder, sum), Some(3));
+        assert_eq!(level_of(&chained, chain_sum), Some(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot reduce with Min")]
+    fn reduce_rejects_unsupported_kind_for_single_node() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        builder.reduce(&[x], OpKind::Min);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn fill_nodes_rejects_too_many_inputs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let _x_squared = builder.mul(&x, &x);
+
+        assert_eq!(
+            builder.fill_nodes(vec![Some(3), None, Some(4)]),
+            Err(BuilderError::TooManyInputs { given: 3, nodes: 2 })
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn fill_nodes_rejects_value_for_constant() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let five = builder.constant(5);
+        let sum = builder.add(&x, &five);
+
+        assert_eq!(
+            builder.fill_nodes(vec![Some(3), Some(6)]),
+            Err(BuilderError::NotAnInput(five))
+        );
+        assert_eq!(
+            builder.fill_nodes(vec![Some(3), None, Some(1)]),
+            Err(BuilderError::NotAnInput(sum))
+        );
+        assert_eq!(builder.get_value(five), Some(5));
+        assert_eq!(builder.get_value(x), None);
+        assert_eq!(
+            builder.fill_inputs(&[(x, 3), (7, 1)]),
+            Err(BuilderError::InvalidNode(7))
+        );
+    }
+
+    #[test]
+    fn to_r1cs_is_satisfied_by_witness() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let one = builder.constant(1);
+        let b = builder.add(&a, &one);
+        let c = builder.hint(|values| values[0] / 8, vec![b]);
+        let eight = builder.constant(8);
+        let c_times_8 = builder.mul(&c, &eight);
+        builder.assert_equal(b, c_times_8);
+
+        let r1cs = builder.to_r1cs();
+        // Two constants, one add, one mul and the assert_equal.
+        assert_eq!(r1cs.len(), 5);
+        assert_eq!(r1cs.public_inputs, vec![a + 1]);
+
+        // The leading 1 is followed by the values of a, one, b, c, eight and c_times_8.
+        assert!(r1cs.is_satisfied(&[1, 7, 1, 8, 1, 8, 8]));
+        // A dishonest hint (c = 2) breaks the mul and assert_equal constraints.
+        assert!(!r1cs.is_satisfied(&[1, 7, 1, 8, 2, 8, 16]));
+    }
+
+    #[test]
+    fn witness_after_fill() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        assert_eq!(builder.witness(), Err(BuilderError::Unfilled(x)));
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        let witness = builder.witness().unwrap();
+        assert_eq!(witness, vec![3, 9, 12, 5, 17]);
+        assert_eq!(witness[x_squared], 9);
+        assert_eq!(witness[y], 17);
+
+        let assignment: Vec<u32> = std::iter::once(1).chain(witness).collect();
+        assert!(builder.to_r1cs().is_satisfied(&assignment));
+    }
+
+    #[test]
+    fn nodes_with_value_after_fill() {
+        let mut builder = Builder::new();
+        let two = builder.constant(2);
+        let three = builder.constant(3);
+        let six = builder.mul(&two, &three);
+        let six_alt = builder.add(&three, &three);
+        let _twelve = builder.add(&six, &six_alt);
+
+        assert!(builder.nodes_with_value(6).is_empty());
+        builder.fill_inputs(&[]).unwrap();
+        assert_eq!(builder.nodes_with_value(6), vec![six, six_alt]);
+        assert_eq!(builder.nodes_with_value(3), vec![three]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn fill_nodes_rejects_cycles() {
+        let mut builder = Builder::new();
+        let a = builder.hint(|values| values[0], vec![1]);
+        let _b = builder.hint(|values| values[0], vec![a]);
+
+        assert!(matches!(builder.validate(), Err(BuilderError::Cycle(_))));
+        assert!(matches!(builder.fill_nodes(vec![]), Err(BuilderError::Cycle(_))));
+    }
+
+    #[test]
+    fn scale_by_constant() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let tripled = builder.scale(&x, 3);
+
+        builder.fill_inputs(&[(x, 7)]).unwrap();
+        assert_eq!(builder.witness().unwrap()[tripled], 21);
+    }
+
+    #[test]
+    fn offset_by_constant() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let shifted = builder.offset(&x, 5);
+
+        builder.fill_inputs(&[(x, 7)]).unwrap();
+        assert_eq!(builder.witness().unwrap()[shifted], 12);
+    }
+
+    #[test]
+    fn trace_constraint_includes_inputs() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let unrelated = builder.init();
+        let one = builder.constant(1);
+        let b = builder.add(&a, &one);
+        let c = builder.hint(|values| values[0] / 8, vec![b]);
+        let eight = builder.constant(8);
+        let c_times_8 = builder.mul(&c, &eight);
+        builder.assert_equal(b, c_times_8);
+
+        builder.fill_inputs(&[(a, 9), (unrelated, 4)]).unwrap();
+        assert!(!builder.check_constraints());
+
+        let trace = builder.trace_constraint(0).unwrap();
+        assert_eq!(
+            trace,
+            vec![
+                (a, Some(9)),
+                (one, Some(1)),
+                (b, Some(10)),
+                (c, Some(1)),
+                (eight, Some(8)),
+                (c_times_8, Some(8)),
+            ]
+        );
+        assert!(!trace.iter().any(|&(id, _)| id == unrelated));
+        assert_eq!(builder.trace_constraint(1), None);
+    }
+
+    #[test]
+    fn assert_outputs_reports_mismatches() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+
+        assert_eq!(builder.get_value(y), Some(17));
+        assert_eq!(builder.assert_outputs(&[(x_squared, 9), (y, 17)]), Ok(()));
+        assert_eq!(
+            builder.assert_outputs(&[(x_squared, 9), (x_squared_plus_x, 13), (y, 18)]),
+            Err(vec![(x_squared_plus_x, 13, Some(12)), (y, 18, Some(17))])
+        );
+
+        // Unfilled and missing nodes are reported instead of panicking.
+        let z = builder.init();
+        assert_eq!(
+            builder.assert_outputs(&[(z, 0), (99, 1)]),
+            Err(vec![(z, 0, None), (99, 1, None)])
+        );
+    }
+
+    #[test]
+    fn evaluate_into_leaves_nodes_untouched() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        let first = builder.evaluate_into(&[(x, 3)]);
+        let second = builder.evaluate_into(&[(x, 4)]);
+        assert_eq!(first, vec![Some(3), Some(9), Some(12), Some(5), Some(17)]);
+        assert_eq!(second[y], Some(25));
+        assert_eq!(builder.evaluate_into(&[])[y], None);
+
+        assert_eq!(builder.get_value(x), None);
+        assert_eq!(builder.get_value(y), None);
+    }
+
+    #[test]
+    fn bool_select_picks_by_bit() {
+        for (bit, expected) in [(1, 10), (0, 20)] {
+            let mut builder = Builder::new();
+            let bit_node = builder.init();
+            let a = builder.constant(10);
+            let b = builder.constant(20);
+            let selected = builder.bool_select(&bit_node, &a, &b);
+
+            builder.fill_inputs(&[(bit_node, bit)]).unwrap();
+            assert_eq!(builder.get_value(selected), Some(expected));
+            assert!(builder.check_constraints());
+
+            let assignment: Vec<u32> = std::iter::once(1).chain(builder.witness().unwrap()).collect();
+            assert!(builder.to_r1cs().is_satisfied(&assignment));
+        }
+    }
+
+    #[test]
+    fn bool_select_rejects_non_boolean_bit() {
+        let mut builder = Builder::new();
+        let bit = builder.init();
+        let a = builder.constant(10);
+        let b = builder.constant(5);
+        let _selected = builder.bool_select(&bit, &a, &b);
+
+        builder.fill_inputs(&[(bit, 2)]).unwrap();
+        assert!(!builder.check_constraints());
+    }
+
+    #[test]
+    fn assert_diff_checks_difference() {
+        let build = || {
+            let mut builder = Builder::new();
+            let a = builder.init();
+            let b = builder.init();
+            builder.assert_diff(a, b, 8);
+            (builder, a, b)
+        };
+
+        let (mut builder, a, b) = build();
+        builder.fill_inputs(&[(a, 10), (b, 18)]).unwrap();
+        assert!(builder.check_constraints());
+
+        let (mut builder, a, b) = build();
+        let err = builder.fill_and_check(&[(a, 10), (b, 20)]).unwrap_err();
+        assert_eq!(err.to_string(), "constraint 0 failed: node 1 (20) != node 3 (18)");
+
+        let (mut builder, a, b) = build();
+        builder.fill_inputs(&[(a, u32::MAX - 8), (b, u32::MAX)]).unwrap();
+        assert!(builder.check_constraints());
+        builder.fill_inputs(&[(a, u32::MAX), (b, 4)]).unwrap();
+        assert!(!builder.check_constraints());
+    }
+
+    #[test]
+    fn assert_diff_holds_modulo_2_32() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let b = builder.init();
+        builder.assert_diff(a, b, 8);
+
+        // Documented caveat: 4 isn't 8 more than u32::MAX - 3, but it is
+        // modulo 2^32, which is all the constraint can express.
+        builder.fill_inputs(&[(a, u32::MAX - 3), (b, 4)]).unwrap();
         assert!(builder.check_constraints());
     }
+
+    #[test]
+    fn fill_nodes_from_iterator() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let product = builder.mul(&x, &y);
+
+        let inputs = vec![(x, 6), (y, 7)];
+        builder.fill_nodes_from(inputs).unwrap();
+        assert_eq!(builder.get_value(product), Some(42));
+    }
+
+    #[test]
+    fn diff_reports_structural_changes() {
+        let build = |use_mul: bool, with_unused: bool| {
+            let mut builder = Builder::new();
+            let x = builder.init();
+            let five = builder.constant(5);
+            let y = if use_mul {
+                builder.mul(&x, &five)
+            } else {
+                builder.add(&x, &five)
+            };
+            let expected = builder.constant(15);
+            builder.assert_equal(y, expected);
+            if with_unused {
+                let _unused = builder.add(&y, &y);
+            }
+            builder
+        };
+
+        let original = build(true, true);
+        assert!(original.diff(&build(true, true)).is_empty());
+
+        // Dropping the unused node at the end.
+        assert_eq!(
+            original.diff(&build(true, false)),
+            GraphDiff {
+                removed_nodes: vec![4],
+                ..GraphDiff::default()
+            }
+        );
+
+        let mut rewritten = build(false, true);
+        let extra = rewritten.constant(1);
+        rewritten.assert_equal(extra, extra);
+        assert_eq!(
+            original.diff(&rewritten),
+            GraphDiff {
+                added_nodes: vec![extra],
+                changed_nodes: vec![2],
+                added_constraints: vec![(extra, extra)],
+                ..GraphDiff::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rom_read_values() {
+        let mut builder = Builder::new();
+        let squares = builder.rom(&[0, 1, 4, 9, 16]);
+        let i = builder.init();
+        let j = builder.init();
+        let square_i = builder.rom_read(&squares, &i).unwrap();
+        let square_j = builder.rom_read(&squares, &j).unwrap();
+
+        builder.fill_inputs(&[(i, 2), (j, 4)]).unwrap();
+        assert_eq!(builder.assert_outputs(&[(square_i, 4), (square_j, 16)]), Ok(()));
+    }
+
+    #[test]
+    fn rom_read_out_of_range() {
+        let mut builder = Builder::new();
+        let table = builder.rom(&[7, 8, 9]);
+        let i = builder.init();
+        let entry = builder.rom_read(&table, &i).unwrap();
+
+        assert_eq!(
+            builder.fill_inputs(&[(i, 3)]),
+            Err(BuilderError::Eval(
+                entry,
+                EvalError::IndexOutOfBounds { index: 3, len: 3 }
+            ))
+        );
+    }
+
+    #[test]
+    fn rom_read_rejects_foreign_handle() {
+        let mut other = Builder::new();
+        other.rom(&[1]);
+        let foreign = other.rom(&[2]);
+
+        let mut builder = Builder::new();
+        builder.rom(&[3]);
+        let i = builder.init();
+        assert_eq!(builder.rom_read(&foreign, &i), Err(BuilderError::InvalidRom(1)));
+    }
+
+    #[test]
+    fn build_freezes_into_graph() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        let graph = builder.build();
+        assert_eq!(graph.len(), 5);
+        assert_eq!(graph.evaluate(&[(x, 3)])[y], Some(17));
+        assert_eq!(graph.evaluate(&[(x, 4)])[y], Some(25));
+        assert_eq!(graph.get_value(five), Some(5));
+        assert_eq!(graph.get_value(y), None);
+    }
+
+    #[test]
+    fn overflowing_ops_flag_overflow() {
+        let mut builder = Builder::new();
+        let max = builder.constant(u32::MAX);
+        let one = builder.constant(1);
+        let two = builder.constant(2);
+        let overflowing_sum = builder.overflowing_add(&max, &one);
+        let fine_product = builder.overflowing_mul(&one, &two);
+
+        builder.fill_inputs(&[]).unwrap();
+        assert_eq!(builder.get_value(overflowing_sum), Some(0));
+        assert!(builder.overflowed(overflowing_sum));
+        assert_eq!(builder.get_value(fine_product), Some(2));
+        assert!(!builder.overflowed(fine_product));
+
+        // At 32 bits a plain `add` doesn't wrap: it fails to fill instead.
+        let mut builder = Builder::new();
+        let max = builder.constant(u32::MAX);
+        let one = builder.constant(1);
+        let plain_sum = builder.add(&max, &one);
+        assert_eq!(
+            builder.fill_inputs(&[]),
+            Err(BuilderError::Eval(plain_sum, EvalError::Overflow))
+        );
+
+        let mut builder = Builder::with_bit_width(8);
+        let x = builder.init();
+        let one = builder.constant(1);
+        let overflowing = builder.overflowing_add(&x, &one);
+        let wrapped = builder.add(&x, &one);
+
+        builder.fill_inputs(&[(x, 255)]).unwrap();
+        assert_eq!(builder.get_value(overflowing), Some(0));
+        assert!(builder.overflowed(overflowing));
+        // Plain nodes wrap without a flag.
+        assert_eq!(builder.get_value(wrapped), Some(0));
+        assert!(!builder.overflowed(wrapped));
+    }
+
+    #[test]
+    fn prefix_sum_running_totals() {
+        let mut builder = Builder::new();
+        let values: Vec<_> = (1..=4).map(|value| builder.constant(value)).collect();
+        let sums = builder.prefix_sum(&values);
+
+        builder.fill_inputs(&[]).unwrap();
+        let sums: Vec<_> = sums.iter().map(|&id| builder.get_value(id)).collect();
+        assert_eq!(sums, vec![Some(1), Some(3), Some(6), Some(10)]);
+    }
+
+    #[test]
+    fn invalid_ids_are_errors() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let _bad_sum = builder.add(&x, &99);
+        assert_eq!(builder.fill_inputs(&[(x, 1)]), Err(BuilderError::InvalidNode(99)));
+        assert_eq!(builder.clone_subgraph(42), Err(BuilderError::InvalidNode(42)));
+        assert_eq!(builder.get_value(42), None);
+        assert!(!builder.is_hint(42));
+        assert!(!builder.overflowed(42));
+
+        let mut builder = Builder::new();
+        let x = builder.init();
+        builder.assert_equal(x, 7);
+        assert_eq!(builder.validate(), Err(BuilderError::InvalidNode(7)));
+        assert_eq!(builder.fill_and_check(&[(x, 1)]), Err(BuilderError::InvalidNode(7)));
+        assert!(!builder.check_constraints());
+    }
+
+    #[test]
+    fn simplify_removes_identities() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let zero = builder.constant(0);
+        let one = builder.constant(1);
+        let x_plus_0 = builder.add(&x, &zero);
+        let times_1 = builder.mul(&one, &x_plus_0);
+        let doubled = builder.add(&times_1, &times_1);
+        let x_times_0 = builder.mul(&x, &zero);
+        let total = builder.add(&x_times_0, &doubled);
+        builder.assert_equal(times_1, x_plus_0);
+
+        builder.simplify();
+        assert_eq!(builder.parents(doubled), Some(&[x, x][..]));
+        assert_eq!(builder.parents(total), Some(&[zero, doubled][..]));
+
+        builder.fill_inputs(&[(x, 4)]).unwrap();
+        assert_eq!(builder.get_value(total), Some(8));
+        assert!(builder.check_constraints());
+    }
+
+    #[test]
+    fn live_node_count_excludes_unused_branch() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let one = builder.constant(1);
+        let b = builder.add(&a, &one);
+        let c = builder.hint(|values| values[0] / 8, vec![b]);
+        let eight = builder.constant(8);
+        let c_times_8 = builder.mul(&c, &eight);
+        builder.assert_equal(b, c_times_8);
+        assert_eq!(builder.live_node_count(), 6);
+
+        let unused = builder.mul(&a, &a);
+        let _unused_sum = builder.add(&unused, &one);
+        assert_eq!(builder.live_node_count(), 6);
+    }
+
+    #[test]
+    fn assert_all_equal_reports_offender() {
+        let mut builder = Builder::new();
+        let two = builder.constant(2);
+        let three = builder.constant(3);
+        let four = builder.constant(4);
+        let six = builder.mul(&two, &three);
+        let six_alt = builder.add(&three, &three);
+        let six_again = builder.add(&two, &four);
+        let seven = builder.add(&three, &four);
+        builder.assert_all_equal(&[six, six_alt, seven, six_again]);
+
+        let Err(BuilderError::Constraint(err)) = builder.fill_and_check(&[]) else {
+            panic!("expected a constraint error");
+        };
+        assert_eq!((err.left, err.right), (six, seven));
+        assert_eq!(err.right_value, Some(7));
+    }
+
+    #[test]
+    fn abs_diff_both_orders() {
+        let mut builder = Builder::new();
+        let three = builder.constant(3);
+        let seven = builder.constant(7);
+        let forward = builder.abs_diff(&three, &seven);
+        let backward = builder.abs_diff(&seven, &three);
+
+        builder.fill_inputs(&[]).unwrap();
+        assert_eq!(builder.get_value(forward), Some(4));
+        assert_eq!(builder.get_value(backward), Some(4));
+    }
+
+    #[test]
+    fn specialize_constants_folds_scalar_mul() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let factor = builder.constant(6);
+        let product = builder.mul(&x, &factor);
+        builder.add_output_group("out", &[product]).unwrap();
+        let expected = builder.evaluate_into(&[(x, 7)]);
+        assert_eq!(builder.live_node_count(), 3);
+
+        builder.specialize_constants();
+        assert_eq!(builder.parents(product), Some(&[x][..]));
+        assert_eq!(builder.live_node_count(), 2);
+        let actual = builder.evaluate_into(&[(x, 7)]);
+        assert_eq!(actual[product], Some(42));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn evaluate_streaming_returns_sink_values() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let sum = builder.add(&x, &y);
+        let product = builder.mul(&sum, &y);
+        let unrelated = builder.init();
+        let _ = builder.add(&unrelated, &x);
+
+        assert_eq!(builder.evaluate_streaming(&[(x, 2), (y, 3), (unrelated, 4)], &[product, sum]), vec![15, 5]);
+        assert_eq!(builder.get_value(product), None);
+    }
+
+    #[test]
+    fn fill_twice_recomputes_values() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared, &five);
+
+        builder.fill_inputs(&[(x, 2)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(9));
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(x_squared), Some(9));
+        assert_eq!(builder.get_value(y), Some(14));
+    }
+
+    #[test]
+    fn to_expression_of_example_1() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        assert_eq!(builder.to_expression(y), "((x0 * x0) + x0) + 5");
+        builder.set_metadata(x, "x".to_string()).unwrap();
+        assert_eq!(builder.to_expression(y), "((x * x) + x) + 5");
+        assert_eq!(builder.to_expression(five), "5");
+    }
+
+    #[test]
+    fn evaluate_lanes_runs_each_lane() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        let values = builder.evaluate_lanes(&[(x, [0, 1, 2, 3])]);
+        assert_eq!(values[y], Some([5, 7, 11, 17]));
+        assert_eq!(values[five], Some([5; 4]));
+    }
+
+    #[test]
+    fn structurally_equal_subexpressions() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let five = builder.constant(5);
+        let squared = builder.mul(&x, &x);
+        let first = builder.add(&squared, &five);
+        let five_again = builder.constant(5);
+        let squared_again = builder.mul(&x, &x);
+        let second = builder.add(&squared_again, &five_again);
+        let y_squared = builder.mul(&y, &y);
+        let third = builder.add(&y_squared, &five);
+
+        assert!(builder.are_structurally_equal(first, second));
+        assert!(!builder.are_structurally_equal(first, third));
+        assert!(!builder.are_structurally_equal(x, y));
+    }
+
+    #[test]
+    fn is_satisfied_without_panicking() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let nine = builder.constant(9);
+        builder.assert_equal(x_squared, nine);
+
+        assert!(!builder.is_satisfied());
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert!(builder.is_satisfied());
+        builder.fill_inputs(&[(x, 4)]).unwrap();
+        assert!(!builder.is_satisfied());
+    }
+
+    #[test]
+    fn paths_through_diamond() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let left = builder.offset(&x, 1);
+        let right = builder.scale(&x, 2);
+        let joined = builder.add(&left, &right);
+
+        let paths = builder.paths(x, joined);
+        assert_eq!(paths, vec![vec![x, left, joined], vec![x, right, joined]]);
+        assert_eq!(builder.paths(joined, x), Vec::<Vec<usize>>::new());
+        assert_eq!(builder.paths(x, x), vec![vec![x]]);
+    }
+
+    #[test]
+    fn apply_registered_op() {
+        let mut builder = Builder::new();
+        builder.register_op("clamp255", |values| values[0].min(255));
+        let x = builder.init();
+        let y = builder.init();
+        let sum = builder.add(&x, &y);
+        let clamped_x = builder.apply("clamp255", &[x]).unwrap();
+        let clamped_sum = builder.apply("clamp255", &[sum]).unwrap();
+        assert_eq!(
+            builder.apply("clamp", &[x]),
+            Err(BuilderError::UnknownOp("clamp".to_string()))
+        );
+
+        builder.fill_inputs(&[(x, 200), (y, 100)]).unwrap();
+        assert_eq!(builder.get_value(clamped_x), Some(200));
+        assert_eq!(builder.get_value(clamped_sum), Some(255));
+        assert_eq!(builder.to_expression(clamped_sum), "clamp255(x0 + x1)");
+    }
+
+    #[test]
+    fn unfilled_nodes_after_missing_input() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_doubled = builder.scale(&x, 2);
+        let y_squared = builder.mul(&y, &y);
+        let sum = builder.add(&x_doubled, &y_squared);
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.unfilled_nodes(), vec![y, y_squared, sum]);
+    }
+
+    #[test]
+    fn evaluate_reuse_across_sweep() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared, &five);
+
+        let mut buffer = Vec::new();
+        for value in 0..100 {
+            builder.evaluate_reuse(&[(x, value)], &mut buffer);
+            assert_eq!(buffer, builder.evaluate_into(&[(x, value)]));
+            assert_eq!(buffer[y], Some(value * value + 5));
+        }
+        builder.evaluate_reuse(&[], &mut buffer);
+        assert_eq!(buffer, vec![None, None, Some(5), None]);
+    }
+
+    #[test]
+    fn degrees_of_fan_out() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let shared = builder.offset(&x, 1);
+        let doubled = builder.scale(&shared, 2);
+        let squared = builder.mul(&shared, &shared);
+        let sum = builder.add(&doubled, &squared);
+
+        let degrees = builder.degrees();
+        assert_eq!(degrees.len(), sum + 1);
+        assert_eq!(degrees[x], (0, 1));
+        assert_eq!(degrees[shared], (2, 3));
+        assert_eq!(degrees[sum], (2, 0));
+    }
+
+    #[test]
+    fn solve_constraints_fills_input() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        builder.assert_equal(x_squared, y);
+        let y_plus_one = builder.offset(&y, 1);
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(y), None);
+
+        builder.solve_constraints(true);
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(9));
+        assert_eq!(builder.get_value(y_plus_one), Some(10));
+
+        builder.fill_inputs(&[(x, 4)]).unwrap();
+        assert_eq!(builder.get_value(y_plus_one), Some(17));
+
+        let err = builder.fill_inputs(&[(x, 3), (y, 8)]).unwrap_err();
+        assert!(matches!(err, BuilderError::Constraint(ref err) if err.index == 0));
+    }
+
+    #[test]
+    fn with_capacity_builds_same_graph() {
+        fn chain(builder: &mut Builder) -> (usize, usize) {
+            let x = builder.init();
+            let mut last = x;
+            for _ in 0..1000 {
+                last = builder.offset(&last, 3);
+            }
+            builder.assert_equal(last, last);
+            (x, last)
+        }
+
+        let mut preallocated = Builder::with_capacity(2001);
+        let mut grown = Builder::new();
+        let (x, last) = chain(&mut preallocated);
+        chain(&mut grown);
+
+        assert!(preallocated.diff(&grown).is_empty());
+        preallocated.fill_inputs(&[(x, 1)]).unwrap();
+        assert_eq!(preallocated.get_value(last), Some(3001));
+    }
+
+    #[test]
+    fn import_exported_constraints() {
+        fn structure(builder: &mut Builder) -> (usize, usize, usize) {
+            let x = builder.init();
+            let x_squared = builder.mul(&x, &x);
+            let nine = builder.constant(9);
+            (x, x_squared, nine)
+        }
+
+        let mut source = Builder::new();
+        let (x, x_squared, nine) = structure(&mut source);
+        source.assert_equal(x_squared, nine);
+        let mut target = Builder::new();
+        structure(&mut target);
+        target.assert_equal(x_squared, x_squared);
+
+        let constraints: Vec<Constraint> = source.export_constraints();
+        assert_eq!(constraints, vec![(x_squared, nine)]);
+        target.import_constraints(constraints);
+        assert!(source.diff(&target).is_empty());
+        target.fill_inputs(&[(x, 4)]).unwrap();
+        assert!(!target.is_satisfied());
+    }
+
+    #[test]
+    fn value_range_after_fill() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let five = builder.constant(5);
+        let _y = builder.add(&x_squared, &five);
+
+        assert_eq!(Builder::new().value_range(), None);
+        assert_eq!(builder.value_range(), Some((5, 5)));
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.value_range(), Some((3, 14)));
+    }
+
+    #[test]
+    fn flatten_associative_collapses_add_chain() {
+        let mut builder = Builder::new();
+        let inputs: Vec<usize> = (0..5).map(|_| builder.init()).collect();
+        let mut sum = inputs[0];
+        for input in &inputs[1..] {
+            sum = builder.add(&sum, input);
+        }
+        builder.add_output_group("out", &[sum]).unwrap();
+        let values: Vec<_> = inputs.iter().copied().zip(1..=5).collect();
+        let expected = builder.evaluate_into(&values)[sum];
+
+        builder.flatten_associative();
+        assert_eq!(builder.parents(sum), Some(&inputs[..]));
+        assert_eq!(builder.to_expression(sum), "x0 + x1 + x2 + x3 + x4");
+        assert_eq!(builder.live_node_count(), 6);
+        assert_eq!(builder.evaluate_into(&values)[sum], expected);
+        assert_eq!(expected, Some(15));
+    }
+
+    #[test]
+    fn value_computes_only_its_cone() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let first = builder.offset(&x_squared, 5);
+        let y_squared = builder.mul(&y, &y);
+        let second = builder.add(&y_squared, &x);
+
+        builder.set_inputs(&[(x, 3), (y, 4)]).unwrap();
+        assert_eq!(builder.get_value(first), None);
+        assert_eq!(builder.value(first), Some(14));
+        assert_eq!(builder.get_value(x_squared), Some(9));
+        assert_eq!(builder.get_value(y_squared), None);
+        assert_eq!(builder.get_value(second), None);
+        assert_eq!(builder.set_inputs(&[(first, 1)]), Err(BuilderError::NotAnInput(first)));
+    }
+
+    #[test]
+    fn value_handles_deep_chains() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let one = builder.constant(1);
+        let mut last = x;
+        for _ in 0..100_000 {
+            last = builder.add(&last, &one);
+        }
+
+        builder.set_inputs(&[(x, 0)]).unwrap();
+        assert_eq!(builder.value(last), Some(100_000));
+    }
+
+    #[test]
+    fn strict_hints_reject_lossy_sqrt() {
+        let mut builder = Builder::new();
+        builder.strict_hints(true);
+        let x = builder.init();
+        let root = builder.hint_f64(|values| (values[0] as f64).sqrt(), vec![x]);
+
+        builder.fill_inputs(&[(x, 16)]).unwrap();
+        assert_eq!(builder.get_value(root), Some(4));
+        assert_eq!(
+            builder.fill_inputs(&[(x, 10)]),
+            Err(BuilderError::Eval(root, EvalError::LossyHint))
+        );
+
+        builder.strict_hints(false);
+        let lenient = builder.hint_f64(|values| (values[0] as f64).sqrt(), vec![x]);
+        assert_eq!(builder.evaluate_into(&[(x, 10)])[lenient], Some(3));
+    }
+
+    #[test]
+    fn constraints_for_node() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let z = builder.init();
+        builder.assert_equal(x, y);
+        builder.assert_equal(y, z);
+        builder.assert_equal(z, x);
+
+        assert_eq!(builder.constraints_for(y), vec![&(x, y), &(y, z)]);
+        assert!(builder.constraints_for(z + 1).is_empty());
+    }
+
+    #[test]
+    fn div_with_constraint_adds_check() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let eight = builder.constant(8);
+        let quotient = builder.div_with_constraint(&a, &eight);
+
+        assert_eq!(builder.constraints_for(a).len(), 1);
+        assert!(builder.unconstrained_hints().is_empty());
+        assert_eq!(builder.fill_and_check(&[(a, 56)]), Ok(()));
+        assert_eq!(builder.get_value(quotient), Some(7));
+        assert!(builder.fill_and_check(&[(a, 57)]).is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let table = builder.rom(&[2, 3, 5, 7, 11]);
+        let mut last = builder.scale(&x, 3);
+        for i in 0..500 {
+            last = match i % 4 {
+                0 => builder.offset(&last, i),
+                1 => builder.overflowing_mul(&last, &x),
+                2 => builder.sub(&last, &x),
+                _ => builder.max(&last, &x),
+            };
+        }
+        let index = builder.constant(3);
+        let entry = builder.rom_read(&table, &index).unwrap();
+        let result = builder.add(&last, &entry);
+        builder.specialize_constants();
+        builder.flatten_associative();
+        builder.assert_equal(result, result);
+
+        let bytes = builder.to_bytes();
+        let decoded = Builder::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert!(builder.diff(&decoded).is_empty());
+        assert_eq!(decoded.evaluate_into(&[(x, 4)]), builder.evaluate_into(&[(x, 4)]));
+
+        let mut with_hint = Builder::new();
+        let y = with_hint.init();
+        let hinted = with_hint.hint(|values| values[0], vec![y]);
+        assert_eq!(
+            Builder::from_bytes(&with_hint.to_bytes()).err(),
+            Some(DecodeError::Unencodable(hinted))
+        );
+        assert_eq!(
+            Builder::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn buffer_assignment_reuses_slots() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let mut last = x;
+        for _ in 0..100 {
+            last = builder.offset(&last, 1);
+        }
+
+        let slots = builder.buffer_assignment();
+        assert_eq!(slots.len(), 201);
+        let mut distinct = slots.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert!(distinct.len() <= 3, "used {} slots", distinct.len());
+
+        let mut buffers = vec![0; distinct.len()];
+        let values = builder.evaluate_into(&[(x, 5)]);
+        for (node_id, value) in values.iter().enumerate() {
+            buffers[slots[node_id]] = value.unwrap();
+        }
+        assert_eq!(buffers[slots[last]], 105);
+    }
+
+    #[test]
+    fn diamonds_detects_reconvergence() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let x = builder.offset(&a, 1);
+        let left = builder.offset(&x, 2);
+        let right = builder.scale(&x, 3);
+        let joined = builder.add(&left, &right);
+        let _after = builder.offset(&joined, 4);
+
+        assert_eq!(builder.diamonds(), vec![(x, joined)]);
+    }
+
+    #[test]
+    fn reset_values_except_keeps_fixed_input() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let product = builder.mul(&x, &y);
+
+        builder.fill_inputs(&[(x, 2), (y, 3)]).unwrap();
+        for y_value in 4..8 {
+            builder.reset_values_except(&[x]);
+            assert_eq!(builder.unfilled_nodes(), vec![y, product]);
+            builder.fill_inputs(&[(y, y_value)]).unwrap();
+            assert_eq!(builder.get_value(product), Some(2 * y_value));
+        }
+    }
+
+    #[test]
+    fn fan_in_size_grows_with_depth() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let shallow = builder.mul(&x, &x);
+        let mut deep = shallow;
+        for _ in 0..3 {
+            deep = builder.add(&deep, &x);
+        }
+
+        assert_eq!(builder.fan_in_size(x), 0);
+        assert_eq!(builder.fan_in_size(shallow), 1);
+        assert_eq!(builder.fan_in_size(deep), 4);
+        assert_eq!(builder.fan_in_size(deep + 1), 0);
+    }
+
+    #[test]
+    fn assert_equal_if_follows_flag() {
+        let mut builder = Builder::new();
+        let flag = builder.init();
+        let a = builder.init();
+        let b = builder.init();
+        builder.assert_equal_if(flag, a, b);
+
+        assert_eq!(builder.fill_and_check(&[(flag, 1), (a, 4), (b, 4)]), Ok(()));
+        assert!(builder.fill_and_check(&[(flag, 1), (a, 4), (b, 5)]).is_err());
+        assert_eq!(builder.fill_and_check(&[(flag, 0), (a, 4), (b, 5)]), Ok(()));
+        assert!(builder.fill_and_check(&[(flag, 2), (a, 4), (b, 5)]).is_err());
+        // Any nonzero flag enforces the equality, not just 1.
+        assert_eq!(builder.fill_and_check(&[(flag, 2), (a, 4), (b, 4)]), Ok(()));
+        assert!(builder.fill_and_check(&[(flag, u32::MAX), (a, 0), (b, 1)]).is_err());
+    }
+
+    #[test]
+    fn bind_input_to_computed_node() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let y = builder.offset(&x_squared, 5);
+        let a = builder.init();
+        let doubled = builder.scale(&a, 2);
+
+        builder.bind_input(x, doubled).unwrap();
+        assert_eq!(builder.parents(x_squared), Some(&[doubled, doubled][..]));
+        builder.fill_inputs(&[(a, 3)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(41));
+        assert_eq!(builder.bind_input(y, a), Err(BuilderError::NotAnInput(y)));
+    }
+
+    #[test]
+    fn bind_input_rejects_source_depending_on_input() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.offset(&x, 1);
+        let z = builder.scale(&y, 2);
+
+        assert_eq!(builder.bind_input(x, z), Err(BuilderError::Cycle(z)));
+        assert_eq!(builder.parents(y).unwrap()[0], x);
+        builder.fill_inputs(&[(x, 4)]).unwrap();
+        assert_eq!(builder.get_value(z), Some(10));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn nondeterministic_hint_is_flagged() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let noisy = builder.hint(
+            move |values| {
+                counter.set(counter.get() + 1);
+                values[0] + counter.get()
+            },
+            vec![x],
+        );
+        let _pure = builder.hint(|values| values[0] * 2, vec![x]);
+
+        assert_eq!(
+            builder.fill_inputs(&[(x, 10)]),
+            Err(BuilderError::Eval(
+                noisy,
+                EvalError::NondeterministicHint { first: 11, second: 12 }
+            ))
+        );
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn sort2_orders_both_ways() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let b = builder.init();
+        let (low, high) = builder.sort2(&a, &b);
+
+        builder.fill_inputs(&[(a, 7), (b, 3)]).unwrap();
+        assert_eq!((builder.get_value(low), builder.get_value(high)), (Some(3), Some(7)));
+        builder.fill_inputs(&[(a, 3), (b, 7)]).unwrap();
+        assert_eq!((builder.get_value(low), builder.get_value(high)), (Some(3), Some(7)));
+    }
+
+    #[test]
+    fn sort2_builds_sorting_network() {
+        let mut builder = Builder::new();
+        let inputs: Vec<_> = (0..4).map(|_| builder.init()).collect();
+        let mut wires = inputs.clone();
+        for (i, j) in [(0, 1), (2, 3), (0, 2), (1, 3), (1, 2)] {
+            (wires[i], wires[j]) = builder.sort2(&wires[i], &wires[j]);
+        }
+
+        for values in [[4, 1, 3, 2], [9, 9, 0, 5], [1, 2, 3, 4], [8, 6, 4, 2]] {
+            let assignments: Vec<_> = inputs.iter().copied().zip(values).collect();
+            builder.fill_inputs(&assignments).unwrap();
+            let mut expected = values;
+            expected.sort();
+            let sorted: Vec<u32> = wires.iter().map(|&id| builder.get_value(id).unwrap()).collect();
+            assert_eq!(sorted, expected);
+        }
+    }
+
+    #[test]
+    fn restore_state_rewinds_values() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let product = builder.mul(&x, &y);
+
+        builder.fill_inputs(&[(x, 3), (y, 4)]).unwrap();
+        let state = builder.save_state();
+        builder.fill_inputs(&[(x, 5), (y, 6)]).unwrap();
+        assert_eq!(builder.get_value(product), Some(30));
+
+        let later = builder.offset(&product, 1);
+        builder.fill_inputs(&[(x, 1), (y, 1)]).unwrap();
+        builder.restore_state(&state);
+        assert_eq!(builder.get_value(x), Some(3));
+        assert_eq!(builder.get_value(y), Some(4));
+        assert_eq!(builder.get_value(product), Some(12));
+        assert_eq!(builder.get_value(later), None);
+    }
+
+    #[test]
+    fn fill_evaluates_each_node_once() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let five = builder.constant(5);
+        let late = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let sum = builder.add(&x_squared, &five);
+        let root = builder.hint(|values| values[0] / 2, vec![sum]);
+        let _y = builder.add(&root, &late);
+
+        builder.fill_inputs(&[(x, 3), (late, 1)]).unwrap();
+        assert_eq!(builder.eval_counts(), vec![0, 0, 0, 1, 1, 1, 1]);
+        builder.fill_inputs(&[(x, 4), (late, 2)]).unwrap();
+        assert_eq!(builder.eval_counts(), vec![0, 0, 0, 1, 1, 1, 1]);
+        builder.set_inputs(&[(x, 1), (late, 0)]).unwrap();
+        assert_eq!(builder.eval_counts(), vec![0; 7]);
+    }
+
+    #[test]
+    fn minimal_failing_set_groups_by_shared_inputs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let z = builder.init();
+        let w = builder.init();
+        let x_plus_y = builder.add(&x, &y);
+        let y_times_z = builder.mul(&y, &z);
+        let w_doubled = builder.scale(&w, 2);
+        let ten = builder.constant(10);
+        let zero = builder.constant(0);
+
+        builder.assert_equal(w_doubled, ten);
+        builder.assert_equal(x, zero);
+        builder.assert_equal(x_plus_y, ten);
+        builder.assert_equal(y_times_z, ten);
+        builder.assert_equal(z, ten);
+
+        builder.fill_inputs(&[(x, 1), (y, 2), (z, 3), (w, 5)]).unwrap();
+        assert_eq!(builder.minimal_failing_set(), vec![1, 2, 3, 4]);
+        builder.fill_inputs(&[(x, 1), (y, 9), (z, 10), (w, 1)]).unwrap();
+        assert_eq!(builder.minimal_failing_set(), vec![0]);
+        builder.fill_inputs(&[(x, 0), (y, 10), (z, 3), (w, 5)]).unwrap();
+        assert_eq!(builder.minimal_failing_set(), vec![3, 4]);
+        builder.fill_inputs(&[(x, 0), (y, 10), (z, 10), (w, 5)]).unwrap();
+        assert_eq!(builder.minimal_failing_set(), vec![3]);
+    }
+
+    #[test]
+    fn reduce_folds_constants() {
+        let mut builder = Builder::new();
+        let values: Vec<_> = (1..=10).map(|value| builder.constant(value)).collect();
+        let sum = builder.reduce(&values, OpKind::Add);
+        assert_eq!(builder.get_value(sum), Some(55));
+        assert!(builder.parents(sum).unwrap().is_empty());
+        assert_eq!(sum, values.len());
+
+        let x = builder.init();
+        let mixed = builder.reduce(&[values[0], x], OpKind::Add);
+        assert_eq!(builder.parents(mixed), Some(&[values[0], x][..]));
+
+        let big = builder.constant(u32::MAX);
+        let overflowing = builder.reduce(&[big, big], OpKind::Add);
+        assert_eq!(builder.parents(overflowing), Some(&[big, big][..]));
+    }
+
+    #[test]
+    fn eval_ranges_reports_possible_overflow() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let product = builder.mul(&x, &y);
+        let shifted = builder.offset(&product, 10);
+        let difference = builder.sub(&shifted, &y);
+        let smaller = builder.min(&x, &y);
+        let root = builder.hint(|values| values[0] / 2, vec![x]);
+
+        let ranges = builder.eval_ranges(&[(x, (1, 100)), (y, (2, 5))]);
+        assert_eq!(ranges[product], (2, 500));
+        assert_eq!(ranges[shifted], (12, 510));
+        assert_eq!(ranges[difference], (7, 508));
+        assert_eq!(ranges[smaller], (1, 5));
+        assert_eq!(ranges[root], (0, u32::MAX as u64));
+
+        let ranges = builder.eval_ranges(&[(x, (0, 70_000)), (y, (0, 70_000))]);
+        assert_eq!(ranges[product], (0, 4_900_000_000));
+        assert!(ranges[product].1 > u32::MAX as u64);
+        assert_eq!(ranges[difference], (0, u32::MAX as u64));
+    }
+
+    #[test]
+    fn eval_ranges_widens_wrapping_nodes() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let sum = builder.overflowing_add(&x, &y);
+        let five = builder.constant(5);
+        let smaller = builder.min(&sum, &five);
+
+        let ranges = builder.eval_ranges(&[(x, (1 << 31, u32::MAX)), (y, (1 << 31, u32::MAX))]);
+        assert_eq!(ranges[sum], (0, u32::MAX as u64));
+        assert_eq!(ranges[smaller], (0, 5));
+
+        builder.fill_inputs(&[(x, 1 << 31), (y, 1 << 31)]).unwrap();
+        assert_eq!(builder.get_value(smaller), Some(0));
+        assert_eq!(builder.eval_ranges(&[(x, (1, 2)), (y, (3, 4))])[sum], (4, 6));
+    }
+
+    #[test]
+    fn hints_by_tag_finds_tagged_hints() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let sqrt = |values: &[u32]| (values[0] as f64).sqrt() as u32;
+        let root_x = builder.hint_tagged("sqrt", sqrt, vec![x]);
+        let quotient = builder.hint_tagged("div", |values| values[0] / values[1], vec![x, y]);
+        let root_y = builder.hint_tagged("sqrt", sqrt, vec![y]);
+        let _untagged = builder.hint(sqrt, vec![y]);
+
+        assert_eq!(builder.hints_by_tag("sqrt"), vec![root_x, root_y]);
+        assert_eq!(builder.hints_by_tag("div"), vec![quotient]);
+        assert!(builder.hints_by_tag("cbrt").is_empty());
+        builder.fill_inputs(&[(x, 16), (y, 4)]).unwrap();
+        assert_eq!(builder.get_value(root_x), Some(4));
+        assert_eq!(builder.get_value(quotient), Some(4));
+    }
+
+    #[test]
+    fn rem_node_uses_runtime_modulus() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let m = builder.init();
+        let remainder = builder.rem_node(&a, &m);
+
+        builder.fill_inputs(&[(a, 17), (m, 5)]).unwrap();
+        assert_eq!(builder.get_value(remainder), Some(2));
+        builder.fill_inputs(&[(a, 3), (m, 7)]).unwrap();
+        assert_eq!(builder.get_value(remainder), Some(3));
+        assert_eq!(
+            builder.fill_inputs(&[(a, 3), (m, 0)]),
+            Err(BuilderError::Eval(remainder, EvalError::DivisionByZero))
+        );
+        assert_eq!(builder.to_expression(remainder), "x0 % x1");
+    }
+
+    #[test]
+    fn canonical_bytes_ignore_build_order() {
+        let mut first = Builder::new();
+        let x = first.init();
+        let y = first.init();
+        let five = first.constant(5);
+        let x_squared = first.mul(&x, &x);
+        let y_plus_five = first.add(&y, &five);
+        let total = first.add(&x_squared, &y_plus_five);
+        first.assert_equal(total, five);
+
+        let mut second = Builder::new();
+        let five = second.constant(5);
+        let x = second.init();
+        let y = second.init();
+        let y_plus_five = second.add(&y, &five);
+        let x_squared = second.mul(&x, &x);
+        let total = second.add(&x_squared, &y_plus_five);
+        second.assert_equal(five, total);
+
+        assert_ne!(first.to_bytes(), second.to_bytes());
+        assert_eq!(first.to_canonical_bytes(), second.to_canonical_bytes());
+        let decoded = Builder::from_bytes(&first.to_canonical_bytes()).unwrap();
+        assert_eq!(decoded.to_canonical_bytes(), first.to_canonical_bytes());
+
+        let mut swapped = Builder::new();
+        let y = swapped.init();
+        let x = swapped.init();
+        let five = swapped.constant(5);
+        let x_squared = swapped.mul(&x, &x);
+        let y_plus_five = swapped.add(&y, &five);
+        let total = swapped.add(&x_squared, &y_plus_five);
+        swapped.assert_equal(total, five);
+        assert_ne!(swapped.to_canonical_bytes(), first.to_canonical_bytes());
+    }
+
+    #[test]
+    fn canonical_bytes_ignore_table_order() {
+        let mut first = Builder::new();
+        let i = first.init();
+        let squares = first.rom(&[0, 1, 4, 9]);
+        let cubes = first.rom(&[0, 1, 8, 27]);
+        let square = first.rom_read(&squares, &i).unwrap();
+        let cube = first.rom_read(&cubes, &i).unwrap();
+        first.assert_equal(square, cube);
+
+        let mut second = Builder::new();
+        let i = second.init();
+        let cubes = second.rom(&[0, 1, 8, 27]);
+        let squares = second.rom(&[0, 1, 4, 9]);
+        let cube = second.rom_read(&cubes, &i).unwrap();
+        let square = second.rom_read(&squares, &i).unwrap();
+        second.assert_equal(cube, square);
+
+        assert_ne!(first.to_bytes(), second.to_bytes());
+        assert_eq!(first.to_canonical_bytes(), second.to_canonical_bytes());
+        let mut decoded = Builder::from_bytes(&second.to_canonical_bytes()).unwrap();
+        assert_eq!(decoded.fill_and_check(&[(0, 1)]), Ok(()));
+        assert!(decoded.fill_and_check(&[(0, 2)]).is_err());
+    }
+
+    #[test]
+    fn load_witness_checks_without_recomputing() {
+        let mut prover = Builder::new();
+        let x = prover.init();
+        let x_squared = prover.mul(&x, &x);
+        let expected = prover.constant(49);
+        prover.assert_equal(x_squared, expected);
+        prover.fill_inputs(&[(x, 7)]).unwrap();
+        let witness = prover.witness().unwrap();
+
+        let mut verifier = Builder::new();
+        let x = verifier.init();
+        let x_squared = verifier.mul(&x, &x);
+        let expected = verifier.constant(49);
+        verifier.assert_equal(x_squared, expected);
+        verifier.load_witness(&witness).unwrap();
+        assert!(verifier.check_constraints());
+
+        let mut tampered = witness.clone();
+        tampered[x_squared] = 50;
+        verifier.load_witness(&tampered).unwrap();
+        assert!(!verifier.check_constraints());
+        assert_eq!(verifier.get_value(x), Some(7));
+
+        tampered[expected] = 50;
+        verifier.load_witness(&tampered).unwrap();
+        assert!(!verifier.check_constraints());
+        let nodes = witness.len();
+        assert_eq!(
+            verifier.load_witness(&witness[..2]),
+            Err(BuilderError::WitnessLength { given: 2, nodes })
+        );
+        tampered.push(0);
+        assert_eq!(
+            verifier.load_witness(&tampered),
+            Err(BuilderError::WitnessLength {
+                given: nodes + 1,
+                nodes
+            })
+        );
+    }
+
+    #[test]
+    fn output_depends_on_lists_relevant_inputs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let z = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let first = builder.add(&x_squared, &y);
+        let z_offset = builder.offset(&z, 3);
+        let second = builder.mul(&z_offset, &y);
+        let constant = builder.constant(4);
+
+        assert_eq!(builder.output_depends_on(first), vec![x, y]);
+        assert_eq!(builder.output_depends_on(second), vec![y, z]);
+        assert_eq!(builder.output_depends_on(z), vec![z]);
+        assert!(builder.output_depends_on(constant).is_empty());
+    }
+
+    #[test]
+    fn horner_matches_expanded_polynomial() {
+        // 4 + 3x + 2x^2 + 5x^3
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let horner = builder.horner(&x, &[4, 3, 2, 5]);
+
+        let x_squared = builder.mul(&x, &x);
+        let x_cubed = builder.mul(&x_squared, &x);
+        let linear = builder.scale(&x, 3);
+        let quadratic = builder.scale(&x_squared, 2);
+        let cubic = builder.scale(&x_cubed, 5);
+        let terms = builder.add(&linear, &quadratic);
+        let terms = builder.add(&terms, &cubic);
+        let expanded = builder.offset(&terms, 4);
+        builder.assert_equal(horner, expanded);
+
+        for value in [0, 1, 2, 7] {
+            builder.fill_inputs(&[(x, value)]).unwrap();
+            assert!(builder.check_constraints());
+        }
+        assert_eq!(builder.get_value(horner), Some(4 + 3 * 7 + 2 * 49 + 5 * 343));
+        let empty = builder.horner(&x, &[]);
+        assert_eq!(builder.get_value(empty), Some(0));
+    }
+
+    #[test]
+    fn unused_constants_are_listed() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let used = builder.offset(&x, 5);
+        let unused = builder.constant(7);
+        let constrained = builder.constant(12);
+        builder.assert_equal(used, constrained);
+        let also_unused = builder.constant_bool(true);
+
+        assert_eq!(builder.unused_constants(), vec![unused, also_unused]);
+    }
+
+    #[test]
+    fn bit_width_wraps_results() {
+        let mut builder = Builder::with_bit_width(8);
+        let a = builder.init();
+        let b = builder.init();
+        let sum = builder.add(&a, &b);
+        let doubled = builder.hint(|values| values[0] * 2, vec![sum]);
+        let expected = builder.constant(44);
+        builder.assert_equal(sum, expected);
+
+        builder.fill_inputs(&[(a, 200), (b, 100)]).unwrap();
+        assert_eq!(builder.get_value(sum), Some(44));
+        assert_eq!(builder.get_value(doubled), Some(88));
+        assert!(builder.check_constraints());
+
+        builder.fill_inputs(&[(a, 44), (b, 256)]).unwrap();
+        assert_eq!(builder.get_value(sum), Some(44));
+        builder.fill_inputs(&[(a, 40), (b, 3)]).unwrap();
+        assert!(!builder.check_constraints());
+    }
+
+    #[test]
+    fn bit_width_applies_to_rewrites_and_encoding() {
+        let mut builder = Builder::with_bit_width(8);
+        let two_hundred = builder.constant(200);
+        let hundred = builder.constant(100);
+        let folded = builder.reduce(&[two_hundred, hundred], OpKind::Add);
+        assert_eq!(builder.get_value(folded), Some(44));
+
+        let a = builder.init();
+        let b = builder.init();
+        let c = builder.init();
+        let offset = builder.add(&a, &hundred);
+        let first = builder.add(&a, &b);
+        let chain = builder.add(&first, &c);
+        let overflowing = builder.overflowing_add(&a, &b);
+        let decoded = Builder::from_bytes(&builder.to_bytes()).unwrap();
+        builder.specialize_constants();
+        builder.flatten_associative();
+        assert_eq!(builder.parents(offset), Some(&[a][..]));
+        assert_eq!(builder.parents(chain), Some(&[a, b, c][..]));
+
+        builder.fill_inputs(&[(a, 200), (b, 100), (c, 0)]).unwrap();
+        assert_eq!(builder.get_value(offset), Some(44));
+        assert_eq!(builder.get_value(chain), Some(44));
+        assert_eq!(builder.get_value(overflowing), Some(44));
+        assert!(builder.overflowed(overflowing));
+
+        let mut decoded = decoded;
+        assert_eq!(decoded.bit_width(), 8);
+        decoded.fill_inputs(&[(a, 200), (b, 100), (c, 0)]).unwrap();
+        assert_eq!(decoded.get_value(offset), Some(44));
+        assert_eq!(decoded.get_value(chain), Some(44));
+        assert!(decoded.overflowed(overflowing));
+    }
+
+    #[test]
+    fn bit_width_multiplication_wraps_without_panicking() {
+        let mut builder = Builder::with_bit_width(20);
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+
+        builder.fill_inputs(&[(x, 70_000)]).unwrap();
+        assert_eq!(
+            builder.get_value(x_squared),
+            Some((4_900_000_000u64 % (1 << 20)) as u32)
+        );
+    }
+
+    #[test]
+    fn bit_width_bounds_eval_ranges() {
+        let mut builder = Builder::with_bit_width(8);
+        let x = builder.init();
+        let hundred = builder.constant(100);
+        let sum = builder.add(&x, &hundred);
+        let small = builder.add(&x, &x);
+
+        let ranges = builder.eval_ranges(&[(x, (200, 200))]);
+        assert_eq!(ranges[sum], (0, 255));
+        assert_eq!(builder.eval_ranges(&[(x, (10, 20))])[small], (20, 40));
+
+        builder.fill_inputs(&[(x, 200)]).unwrap();
+        let value = u64::from(builder.get_value(sum).unwrap());
+        assert!(ranges[sum].0 <= value && value <= ranges[sum].1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot compile a 8-bit graph to R1CS")]
+    fn bit_width_graph_has_no_r1cs() {
+        let mut builder = Builder::with_bit_width(8);
+        let x = builder.init();
+        let _doubled = builder.add(&x, &x);
+        builder.to_r1cs();
+    }
+
+    #[test]
+    fn trace_records_fill_order() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(
+            builder.trace(),
+            vec![(x, 3), (x_squared, 9), (x_squared_plus_x, 12), (y, 17)]
+        );
+
+        builder.fill_inputs(&[(x, 4)]).unwrap();
+        let trace = builder.trace();
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[3], (y, 25));
+    }
+
+    #[test]
+    fn single_output_graph() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let y = builder.offset(&x_squared, 5);
+        assert!(builder.is_single_output());
+
+        // A constant used only by a constraint is an output of its own.
+        let ten = builder.constant(10);
+        builder.assert_equal(y, ten);
+        assert!(!builder.is_single_output());
+    }
+
+    #[test]
+    fn two_output_graph() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let _x_squared = builder.mul(&x, &x);
+        let _x_doubled = builder.add(&x, &x);
+        assert!(!builder.is_single_output());
+        assert!(!Builder::new().is_single_output());
+    }
+
+    #[test]
+    fn dynamic_hint_reads_ancestor_values() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let y = builder.offset(&x_squared, 1);
+        let read = builder.hint_dynamic(move |builder| builder.get_value(x_squared).unwrap() * 10, vec![y]);
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(read), Some(90));
+        assert_eq!(builder.parents(read), Some(&[y][..]));
+        assert_eq!(builder.evaluate_into(&[(x, 3)])[read], None);
+    }
+
+    #[test]
+    fn estimated_cost_uses_weights() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let xy = builder.mul(&x, &y);
+        let sum = builder.add(&x_squared, &xy);
+        let _y = builder.offset(&sum, 5);
+        let _smaller = builder.min(&x, &y);
+        let _root = builder.hint(|values| values[0] / 2, vec![sum]);
+
+        let weights = HashMap::from([(OpKind::Mul, 3), (OpKind::Add, 1)]);
+        assert_eq!(builder.estimated_cost(&weights), 3 + 3 + 1 + 1);
+        let weights = HashMap::from([(OpKind::Mul, 5), (OpKind::Min, 2)]);
+        assert_eq!(builder.estimated_cost(&weights), 5 + 5 + 2);
+        let weights = HashMap::from([(OpKind::Mul, 5), (OpKind::Min, 2), (OpKind::Hint, 10)]);
+        assert_eq!(builder.estimated_cost(&weights), 5 + 5 + 2 + 10);
+        assert_eq!(builder.estimated_cost(&HashMap::new()), 0);
+    }
+
+    #[test]
+    fn components_split_disconnected_subgraphs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let two = builder.constant(2);
+        let x_squared = builder.mul(&x, &x);
+        let y_doubled = builder.mul(&y, &two);
+        let first = builder.add(&x_squared, &x);
+
+        assert_eq!(
+            builder.components(),
+            vec![vec![x, x_squared, first], vec![y, two, y_doubled]]
+        );
+
+        builder.assert_equal(first, y_doubled);
+        assert_eq!(builder.components().len(), 1);
+    }
+
+    #[test]
+    fn try_hint_rejects_missing_dependency() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+
+        assert_eq!(
+            builder.try_hint(|values| values[0], vec![x, 5]),
+            Err(BuilderError::InvalidNode(5))
+        );
+        assert_eq!(builder.unfilled_nodes(), vec![x]);
+        let root = builder.try_hint(|values| values[0] / 2, vec![x]).unwrap();
+        builder.fill_inputs(&[(x, 8)]).unwrap();
+        assert_eq!(builder.get_value(root), Some(4));
+    }
+
+    #[test]
+    fn mul_depth_counts_nested_multiplications() {
+        let mut builder = Builder::new();
+        assert_eq!(builder.mul_depth(), 0);
+        let x = builder.init();
+        let y = builder.init();
+        let sum = builder.add(&x, &y);
+        assert_eq!(builder.mul_depth(), 0);
+
+        // (x * y) * ((x + y) * (x + y)) + x^2 is 2 multiplications deep.
+        let xy = builder.mul(&x, &y);
+        let sum_squared = builder.mul(&sum, &sum);
+        let product = builder.mul(&xy, &sum_squared);
+        let x_squared = builder.mul(&x, &x);
+        let _total = builder.add(&product, &x_squared);
+        assert_eq!(builder.mul_depth(), 2);
+
+        let quartic = builder.mul(&sum_squared, &sum_squared);
+        let deeper = builder.mul(&quartic, &product);
+        assert_eq!(builder.mul_depth(), 3);
+        let _scaled = builder.scale(&deeper, 3);
+        assert_eq!(builder.mul_depth(), 4);
+    }
+
+    #[test]
+    fn run_bundles_outputs_and_verdict() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let m = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let y = builder.offset(&x_squared, 5);
+        let remainder = builder.rem_node(&x, &m);
+        let expected = builder.constant(14);
+        builder.assert_equal(y, expected);
+
+        assert_eq!(
+            builder.run(&[(x, 3), (m, 2)]),
+            RunResult {
+                outputs: vec![(y, Some(14)), (remainder, Some(1)), (expected, Some(14))],
+                satisfied: true,
+                error: None,
+            }
+        );
+
+        let result = builder.run(&[(x, 4), (m, 2)]);
+        assert_eq!(result.outputs[0], (y, Some(21)));
+        assert!(!result.satisfied);
+        assert_eq!(result.error, None);
+
+        let result = builder.run(&[(x, 3), (m, 0)]);
+        assert_eq!(result.outputs[1], (remainder, None));
+        assert_eq!(
+            result.error,
+            Some(BuilderError::Eval(remainder, EvalError::DivisionByZero))
+        );
+    }
+
+    #[test]
+    fn run_reports_overflow_and_rejected_inputs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let max = builder.constant(u32::MAX);
+        let sum = builder.add(&x, &max);
+        let zero = builder.constant(0);
+        builder.assert_equal(y, zero);
+
+        let result = builder.run(&[(x, 0), (y, 0)]);
+        assert_eq!(result.outputs[1], (sum, Some(u32::MAX)));
+        assert!(result.satisfied);
+
+        let result = builder.run(&[(x, 1), (y, 0)]);
+        assert_eq!(result.outputs[1], (sum, None));
+        assert!(!result.satisfied);
+        assert_eq!(result.error, Some(BuilderError::Eval(sum, EvalError::Overflow)));
+
+        builder.run(&[(x, 0), (y, 0)]);
+        let result = builder.run(&[(sum, 7)]);
+        assert_eq!(result.outputs[1], (sum, None));
+        assert!(!result.satisfied);
+        assert_eq!(result.error, Some(BuilderError::NotAnInput(sum)));
+    }
+
+    #[test]
+    fn op_log_matches_for_identical_builds() {
+        let build = |extra: bool| {
+            let mut builder = Builder::new();
+            let x = builder.init();
+            let x_squared = builder.mul(&x, &x);
+            let y = builder.offset(&x_squared, 5);
+            let _root = builder.hint(|values| values[0] / 2, vec![y]);
+            if extra {
+                builder.add(&y, &x);
+            }
+            builder.op_log()
+        };
+
+        assert_eq!(build(false), build(false));
+        assert_ne!(build(false), build(true));
+        assert_eq!(
+            build(false),
+            vec![
+                OpLogEntry::Input,
+                OpLogEntry::Operation(OpKind::Mul, vec![0, 0]),
+                OpLogEntry::Constant(5),
+                OpLogEntry::Operation(OpKind::Add, vec![1, 2]),
+                OpLogEntry::Hint(vec![3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn constraint_penalty_measures_violation() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let sixteen = builder.constant(16);
+        let ten = builder.constant(10);
+        builder.assert_equal(x_squared, sixteen);
+        builder.assert_equal(ten, y);
+
+        builder.fill_inputs(&[(x, 4), (y, 10)]).unwrap();
+        assert_eq!(builder.constraint_penalty(), 0);
+        builder.fill_inputs(&[(x, 5), (y, 7)]).unwrap();
+        assert_eq!(builder.constraint_penalty(), 9 + 3);
+        builder.fill_inputs(&[(x, 3), (y, 12)]).unwrap();
+        assert_eq!(builder.constraint_penalty(), 7 + 2);
+    }
+
+    #[test]
+    fn set_constant_updates_next_fill() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let five = builder.constant(5);
+        let x_squared = builder.mul(&x, &x);
+        let y = builder.add(&x_squared, &five);
+
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(14));
+        builder.set_constant(five, 10).unwrap();
+        assert_eq!(builder.get_value(five), Some(10));
+        assert_eq!(builder.get_value(x_squared), Some(9));
+        assert_eq!(builder.get_value(y), None);
+        builder.fill_inputs(&[(x, 3)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(19));
+
+        assert_eq!(builder.set_constant(x, 1), Err(BuilderError::NotAConstant(x)));
+        assert_eq!(builder.set_constant(y, 1), Err(BuilderError::NotAConstant(y)));
+        assert_eq!(builder.set_constant(42, 1), Err(BuilderError::InvalidNode(42)));
+    }
+
+    #[test]
+    fn restore_state_undoes_set_constant() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let five = builder.constant(5);
+        let y = builder.add(&x, &five);
+
+        builder.fill_inputs(&[(x, 1)]).unwrap();
+        let state = builder.save_state();
+        builder.set_constant(five, 7).unwrap();
+        let later = builder.constant(9);
+        builder.restore_state(&state);
+        assert_eq!(builder.get_value(five), Some(5));
+        assert_eq!(builder.get_value(y), Some(6));
+        assert_eq!(builder.get_value(later), Some(9));
+
+        builder.fill_inputs(&[(x, 1)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(6));
+    }
+
+    #[test]
+    fn set_constant_rejects_folded_constants() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let scaled = builder.scale(&x, 3);
+        let three = builder.parents(scaled).unwrap()[1];
+        builder.specialize_constants();
+        assert_eq!(
+            builder.set_constant(three, 10),
+            Err(BuilderError::FoldedConstant(three))
+        );
+        builder.fill_inputs(&[(x, 2)]).unwrap();
+        assert_eq!(builder.get_value(scaled), Some(6));
+
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let zero = builder.constant(0);
+        let y = builder.add(&x, &zero);
+        let unused = builder.constant(7);
+        builder.simplify();
+        assert_eq!(builder.set_constant(zero, 1), Err(BuilderError::FoldedConstant(zero)));
+        builder.set_constant(unused, 8).unwrap();
+        builder.fill_inputs(&[(x, 2)]).unwrap();
+        assert_eq!(builder.get_value(y), Some(2));
+
+        let mut builder = Builder::new();
+        let a = builder.constant(2);
+        let b = builder.constant(3);
+        builder.reduce(&[a, b], OpKind::Mul);
+        assert_eq!(builder.set_constant(a, 4), Err(BuilderError::FoldedConstant(a)));
+        assert_eq!(builder.set_constant(b, 4), Err(BuilderError::FoldedConstant(b)));
+    }
+
+    #[test]
+    #[should_panic(expected = "a product of 3 factors")]
+    fn flattened_product_has_no_r1cs() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let z = builder.init();
+        let xy = builder.mul(&x, &y);
+        let _xyz = builder.mul(&xy, &z);
+        builder.flatten_associative();
+        builder.to_r1cs();
+    }
+
+    #[test]
+    fn check_constraints_handles_unfilled_nodes() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let five = builder.constant(5);
+        builder.assert_equal(x, five);
+
+        assert!(!builder.check_constraints());
+        builder.fill_inputs(&[(x, 5)]).unwrap();
+        assert!(builder.check_constraints());
+    }
+
+    #[test]
+    fn check_constraints_fails_when_both_sides_unfilled() {
+        let mut builder = Builder::new();
+        let a = builder.init();
+        let one = builder.constant(1);
+        let b = builder.add(&a, &one);
+        let c = builder.hint(|values| values[0] / 8, vec![b]);
+        let eight = builder.constant(8);
+        let c_times_8 = builder.mul(&c, &eight);
+        builder.assert_equal(b, c_times_8);
+
+        builder.fill_inputs(&[]).unwrap();
+        assert!(!builder.check_constraints());
+        assert!(!builder.is_satisfied());
+    }
+
+    #[cfg(feature = "record")]
+    #[test]
+    fn replay_recorded_build() {
+        use cgl::RecordedOp;
+
+        let mut builder = Builder::recording();
+        let x = builder.init();
+        let y = builder.init();
+        let table = builder.rom(&[3, 5, 7]);
+        let entry = builder.rom_read(&table, &y).unwrap();
+        let scaled = builder.scale(&x, 4);
+        let sum = builder.add(&scaled, &entry);
+        let copy = builder.clone_subgraph(sum).unwrap();
+        builder.assert_equal(sum, copy);
+
+        let text: Vec<String> = builder.recorded_ops().iter().map(|op| op.to_string()).collect();
+        assert_eq!(text[..4], ["init", "init", "rom 3 5 7", "rom_read 0 1"]);
+        let parsed: Vec<RecordedOp> = text.iter().map(|line| line.parse().unwrap()).collect();
+        assert_eq!(parsed, builder.recorded_ops());
+
+        let replayed = Builder::replay(&parsed).unwrap();
+        assert!(builder.diff(&replayed).is_empty());
+        assert_eq!(replayed.recorded_ops(), builder.recorded_ops());
+
+        let mut with_hint = Builder::recording();
+        let z = with_hint.init();
+        with_hint.hint(|values| values[0] / 2, vec![z]);
+        assert_eq!(
+            Builder::replay(with_hint.recorded_ops()).err(),
+            Some(BuilderError::UnreplayableHint(1))
+        );
+    }
+
+    #[cfg(feature = "record")]
+    #[test]
+    fn replay_keeps_bit_width() {
+        use cgl::RecordedOp;
+
+        let mut builder = Builder::recording_with_bit_width(8);
+        let a = builder.init();
+        let b = builder.init();
+        let sum = builder.add(&a, &b);
+        assert_eq!(builder.recorded_ops()[0].to_string(), "bit_width 8");
+
+        let mut replayed = Builder::replay(builder.recorded_ops()).unwrap();
+        assert_eq!(replayed.bit_width(), 8);
+        assert_eq!(replayed.recorded_ops(), builder.recorded_ops());
+        replayed.fill_inputs(&[(a, 200), (b, 100)]).unwrap();
+        assert_eq!(replayed.get_value(sum), Some(44));
+
+        let ops: Vec<RecordedOp> = ["init", "bit_width 8"]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(
+            Builder::replay(&ops).err(),
+            Some(BuilderError::MisplacedBitWidth(1))
+        );
+        assert!("bit_width 33".parse::<RecordedOp>().is_err());
+    }
+
+    #[cfg(feature = "record")]
+    #[test]
+    fn replay_rejects_read_from_missing_table() {
+        use cgl::RecordedOp;
+
+        let ops: Vec<RecordedOp> = ["init", "rom_read 5 0"]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(Builder::replay(&ops).err(), Some(BuilderError::InvalidRom(5)));
+    }
+
+    #[cfg(feature = "record")]
+    #[test]
+    fn replay_rejects_missing_nodes() {
+        use cgl::RecordedOp;
+
+        for (lines, missing) in [
+            (&["init", "add 0 7"][..], 7),
+            (&["init", "overflowing_mul 3 0"][..], 3),
+            (&["init", "init", "assert_equal 1 2"][..], 2),
+        ] {
+            let ops: Vec<RecordedOp> = lines.iter().map(|line| line.parse().unwrap()).collect();
+            assert_eq!(Builder::replay(&ops).err(), Some(BuilderError::InvalidNode(missing)));
+        }
+    }
+
+    #[test]
+    fn assert_boolean_accepts_bits_only() {
+        for (value, is_boolean) in [(0, true), (1, true), (2, false), (70_000, false), (u32::MAX, false)] {
+            let mut builder = Builder::new();
+            let yes = builder.constant_bool(true);
+            let no = builder.constant_bool(false);
+            let x = builder.init();
+            builder.assert_boolean(yes);
+            builder.assert_boolean(no);
+            builder.assert_boolean(x);
+
+            builder.fill_inputs(&[(x, value)]).unwrap();
+            assert_eq!(builder.get_value(yes), Some(1));
+            assert_eq!(builder.get_value(no), Some(0));
+            assert_eq!(builder.check_constraints(), is_boolean);
+        }
+    }
+
+    #[test]
+    fn last_fill_rounds_grows_with_backward_chain() {
+        // Each hint depends on the node created right after it, so every
+        // sweep can only fill one more hint.
+        let backward_chain = |length: usize| {
+            let mut builder = Builder::new();
+            for id in 0..length {
+                builder.hint(|values| values[0] + 1, vec![id + 1]);
+            }
+            let x = builder.init();
+            builder.fill_inputs(&[(x, 0)]).unwrap();
+            assert_eq!(builder.get_value(0), Some(length as u32));
+            builder.last_fill_rounds()
+        };
+        assert_eq!(backward_chain(3), 3);
+        assert_eq!(backward_chain(6), 6);
+
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let chain = (0..6).fold(x, |acc, _| builder.offset(&acc, 1));
+        builder.fill_inputs(&[(x, 0)]).unwrap();
+        assert_eq!(builder.get_value(chain), Some(6));
+        assert_eq!(builder.last_fill_rounds(), 1);
+    }
+
+    #[test]
+    fn metadata_set_and_get() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        builder.set_metadata(x, "x".to_string()).unwrap();
+        builder.set_metadata(x_squared, "x^2 (line 3)".to_string()).unwrap();
+
+        assert_eq!(builder.metadata(x), Some("x".to_string()));
+        assert_eq!(builder.metadata(x_squared), Some("x^2 (line 3)".to_string()));
+        let copy = builder.clone_subgraph(x_squared).unwrap();
+        assert_eq!(builder.metadata(copy), Some("x^2 (line 3)".to_string()));
+
+        builder.set_metadata(x, "input x".to_string()).unwrap();
+        assert_eq!(builder.metadata(x), Some("input x".to_string()));
+        assert_eq!(
+            builder.set_metadata(99, "missing".to_string()),
+            Err(BuilderError::InvalidNode(99))
+        );
+        assert_eq!(builder.metadata(99), None);
+    }
+
+    #[test]
+    fn clamp_to_range() {
+        for (value, expected) in [(2, 5), (7, 7), (12, 10)] {
+            let mut builder = Builder::new();
+            let x = builder.init();
+            let clamped = builder.clamp(&x, 5, 10);
+
+            builder.fill_inputs(&[(x, value)]).unwrap();
+            assert_eq!(builder.get_value(clamped), Some(expected));
+        }
+    }
+
+    #[test]
+    fn unused_inputs_reports_dangling_input() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let forgotten = builder.init();
+        let checked = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        builder.assert_equal(checked, x_squared);
+
+        assert_eq!(builder.unused_inputs(), vec![forgotten]);
+    }
+
+    #[test]
+    fn compile_evaluates_repeatedly() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_squared_plus_x = builder.add(&x_squared, &x);
+        let five = builder.constant(5);
+        let y = builder.add(&x_squared_plus_x, &five);
+
+        let f = builder.compile();
+        assert_eq!(f(&[(x, 3)]), vec![3, 9, 12, 5, 17]);
+        assert_eq!(f(&[(x, 4)])[y], 25);
+        assert_eq!(f(&[(x, 0)])[y], 5);
+        assert_eq!(builder.get_value(y), None);
+    }
+
+    #[test]
+    fn output_groups_are_separate() {
+        let mut builder = Builder::new();
+        let x = builder.init();
+        let y = builder.init();
+        let x_squared = builder.mul(&x, &x);
+        let x_cubed = builder.mul(&x_squared, &x);
+        let y_doubled = builder.add(&y, &y);
+        let _unused = builder.add(&x, &y);
+
+        builder.add_output_group("powers", &[x_squared]).unwrap();
+        builder.add_output_group("doubles", &[y_doubled]).unwrap();
+        builder.add_output_group("powers", &[x_cubed]).unwrap();
+        assert_eq!(
+            builder.add_output_group("broken", &[42]),
+            Err(BuilderError::InvalidNode(42))
+        );
+
+        assert_eq!(builder.outputs_of("powers"), Some(&[x_squared, x_cubed][..]));
+        assert_eq!(builder.outputs_of("doubles"), Some(&[y_doubled][..]));
+        assert_eq!(builder.outputs_of("broken"), None);
+        assert_eq!(builder.live_node_count(), 5);
+    }
+
+    #[test]
+    fn verify_reports_each_phase() {
+        let build = || {
+            let mut builder = Builder::new();
+            let a = builder.init();
+            let one = builder.constant(1);
+            let b = builder.add(&a, &one);
+            let c = builder.hint(|values| values[0] / 8, vec![b]);
+            let eight = builder.constant(8);
+            let c_times_8 = builder.mul(&c, &eight);
+            builder.assert_equal(b, c_times_8);
+            (builder, a)
+        };
+
+        let (mut builder, a) = build();
+        assert_eq!(builder.verify(&[(a, 7)]), Ok(()));
+        let (mut builder, a) = build();
+        assert_eq!(builder.verify(&[]), Err(BuilderError::MissingInput(a)));
+        let (mut builder, a) = build();
+        assert!(matches!(builder.verify(&[(a, 9)]), Err(BuilderError::Constraint(_))));
+
+        let (mut cyclic, a) = build();
+        let first = cyclic.hint(|values| values[0], vec![7]);
+        let _second = cyclic.hint(|values| values[0], vec![first]);
+        assert!(matches!(cyclic.verify(&[(a, 7)]), Err(BuilderError::Cycle(_))));
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random_inputs_fill_graph() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let build = || {
+            let mut builder = Builder::new();
+            let x = builder.init();
+            let _five = builder.constant(5);
+            let y = builder.init();
+            let low = builder.min(&x, &y);
+            let high = builder.max(&x, &y);
+            let spread = builder.sub(&high, &low);
+            let _clamped = builder.clamp(&spread, 0, 100);
+            (builder, x, y)
+        };
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..10 {
+            let (mut builder, x, y) = build();
+            let inputs = builder.random_inputs(&mut rng);
+            let handles: Vec<_> = inputs.iter().map(|&(id, _)| id).collect();
+            assert_eq!(handles, vec![x, y]);
+
+            builder.fill_inputs(&inputs).unwrap();
+            assert!(builder.witness().is_ok());
+        }
+    }
 }